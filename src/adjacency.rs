@@ -0,0 +1,95 @@
+use crate::tmux::TmuxPane;
+
+/// Indices of the panes sharing an edge with each pane, within its window
+pub fn pane_neighbors(panes: &[TmuxPane]) -> Vec<Vec<usize>> {
+    (0..panes.len())
+        .map(|i| {
+            (0..panes.len())
+                .filter(|&j| j != i && touches(&panes[i], &panes[j]))
+                .collect()
+        })
+        .collect()
+}
+
+// tmux separates panes with a one cell border, so panes that touch are one
+// cell apart along one axis and overlap along the other
+fn touches(a: &TmuxPane, b: &TmuxPane) -> bool {
+    if a.window_id != b.window_id {
+        return false;
+    }
+
+    let overlaps = |start_a: u32, len_a: u32, start_b: u32, len_b: u32| {
+        start_a < start_b + len_b && start_b < start_a + len_a
+    };
+    let side_by_side = (a.x + a.width + 1 == b.x || b.x + b.width + 1 == a.x)
+        && overlaps(a.y, a.height, b.y, b.height);
+    let stacked = (a.y + a.height + 1 == b.y || b.y + b.height + 1 == a.y)
+        && overlaps(a.x, a.width, b.x, b.width);
+
+    side_by_side || stacked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tmux::fake;
+
+    fn pane(window_id: &str, x: u32, y: u32, width: u32, height: u32) -> TmuxPane {
+        TmuxPane {
+            x,
+            y,
+            width,
+            height,
+            ..fake::pane(&format!("%{}{}", x, y), window_id)
+        }
+    }
+
+    #[test]
+    fn panes_sharing_an_edge_touch() {
+        let left = pane("@1", 0, 0, 40, 24);
+        let right = pane("@1", 41, 0, 39, 24);
+        assert!(touches(&left, &right) && touches(&right, &left));
+
+        let top = pane("@1", 0, 0, 80, 11);
+        let bottom = pane("@1", 0, 12, 80, 12);
+        assert!(touches(&top, &bottom) && touches(&bottom, &top));
+    }
+
+    #[test]
+    fn only_the_one_cell_border_counts() {
+        let left = pane("@1", 0, 0, 40, 24);
+        assert!(touches(&left, &pane("@1", 41, 0, 39, 24)));
+        assert!(!touches(&left, &pane("@1", 40, 0, 40, 24)));
+        assert!(!touches(&left, &pane("@1", 42, 0, 38, 24)));
+    }
+
+    #[test]
+    fn corner_contact_is_not_adjacency() {
+        let top_left = pane("@1", 0, 0, 40, 11);
+        let bottom_right = pane("@1", 41, 12, 39, 12);
+        assert!(!touches(&top_left, &bottom_right));
+        assert!(!touches(&bottom_right, &top_left));
+    }
+
+    #[test]
+    fn panes_in_other_windows_never_touch() {
+        let left = pane("@1", 0, 0, 40, 24);
+        let right = pane("@2", 41, 0, 39, 24);
+        assert!(!touches(&left, &right));
+    }
+
+    #[test]
+    fn grid_neighbors() {
+        // 2x2 grid: each pane touches the two beside it, not the diagonal one
+        let panes = [
+            pane("@1", 0, 0, 40, 11),
+            pane("@1", 41, 0, 39, 11),
+            pane("@1", 0, 12, 40, 12),
+            pane("@1", 41, 12, 39, 12),
+        ];
+        assert_eq!(
+            pane_neighbors(&panes),
+            [vec![1, 2], vec![0, 3], vec![0, 3], vec![1, 2]]
+        );
+    }
+}
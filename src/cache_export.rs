@@ -0,0 +1,273 @@
+use crate::color::{to_hex, Oklch};
+use crate::color_cache::{CachedColor, ColorCache};
+use crate::color_key::KeyStrategy;
+use crate::kitty::parse_hex_color;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use image::Rgb;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Machine independent form of the color cache, meant to be checked into
+/// dotfiles and shared. Only keys, colors and pins are carried over, along
+/// with the session hues of session families.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PortableCache {
+    /// Strategy the keys were made with; keys only match under the same one
+    pub key_strategy: KeyStrategy,
+    pub colors: BTreeMap<String, PortableColor>,
+    /// OKLCH hue of each session by name, in degrees. Imported hues are
+    /// pinned.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub session_hues: BTreeMap<String, f32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PortableColor {
+    /// `#rrggbb`
+    pub color: String,
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+/// What happens when an imported key already has a local color
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MergeStrategy {
+    /// Keep the local color
+    Keep,
+    /// Replace it with the imported color
+    Overwrite,
+    /// The pinned side wins; ties keep the local color
+    PreferPinned,
+}
+
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub added: usize,
+    pub replaced: usize,
+    pub kept: usize,
+}
+
+impl PortableCache {
+    /// Session hues are kept by session id, which `session_names` maps to
+    /// the portable name; hues of sessions that are gone are left out
+    pub fn from_cache(
+        cache: &ColorCache,
+        pinned_only: bool,
+        session_names: &HashMap<String, String>,
+    ) -> Self {
+        let colors = cache
+            .colors
+            .iter()
+            .filter(|(_, cached_color)| !pinned_only || cached_color.pinned)
+            .map(|(key, cached_color)| {
+                (
+                    key.clone(),
+                    PortableColor {
+                        color: to_hex(Rgb(cached_color.rgb)),
+                        pinned: cached_color.pinned,
+                    },
+                )
+            })
+            .collect();
+
+        // Session hues are few and shared on purpose, so all of them go.
+        // Imported ones are already by name and win over the live sessions'.
+        let mut session_hues = BTreeMap::new();
+        for (session, &hue) in &cache.session_hues {
+            match session_names.get(session) {
+                Some(name) => {
+                    session_hues.entry(name.clone()).or_insert(hue);
+                }
+                None if session.starts_with('$') => {}
+                None => {
+                    session_hues.insert(session.clone(), hue);
+                }
+            }
+        }
+        for session in &cache.pinned_sessions {
+            if let Some(&hue) = cache.session_hues.get(session) {
+                session_hues.insert(session.clone(), hue);
+            }
+        }
+
+        Self {
+            key_strategy: cache.key_strategy,
+            colors,
+            session_hues,
+        }
+    }
+
+    pub fn from_json(content: &str) -> Result<Self> {
+        serde_json::from_str(content).context("Failed to parse exported color cache")
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).context("Failed to serialize color cache")
+    }
+
+    /// `session_names` maps the ids of live sessions to their names, so an
+    /// imported session hue meets the local hue of the same session
+    pub fn merge_into(
+        self,
+        cache: &mut ColorCache,
+        strategy: MergeStrategy,
+        session_names: &HashMap<String, String>,
+    ) -> Result<ImportSummary> {
+        let mut summary = ImportSummary::default();
+
+        for (key, portable) in self.colors {
+            let rgb = parse_hex_color(&portable.color)
+                .with_context(|| format!("Invalid color '{}' for {}", portable.color, key))?;
+
+            let replace = match cache.colors.get(&key) {
+                None => true,
+                Some(local) => match strategy {
+                    MergeStrategy::Keep => false,
+                    MergeStrategy::Overwrite => true,
+                    MergeStrategy::PreferPinned => portable.pinned && !local.pinned,
+                },
+            };
+
+            if !replace {
+                summary.kept += 1;
+                continue;
+            }
+
+            if cache.colors.contains_key(&key) {
+                summary.replaced += 1;
+            } else {
+                summary.added += 1;
+            }
+            let mut cached_color = CachedColor::new(rgb, Oklch::from_rgb(rgb).h, portable.pinned);
+            if let Some(local) = cache.colors.get(&key) {
+                cached_color.created_at = local.created_at;
+            }
+            cache.colors.insert(key, cached_color);
+        }
+
+        for (session, hue) in self.session_hues {
+            if !hue.is_finite() {
+                anyhow::bail!("Invalid hue {} for session '{}'", hue, session);
+            }
+
+            // Imported hues count as pinned
+            let local = cache.session_hues.get(&session).or_else(|| {
+                session_names
+                    .iter()
+                    .find(|(_, name)| **name == session)
+                    .and_then(|(id, _)| cache.session_hues.get(id))
+            });
+            let replace = match local {
+                None => true,
+                Some(_) => match strategy {
+                    MergeStrategy::Keep => false,
+                    MergeStrategy::Overwrite => true,
+                    MergeStrategy::PreferPinned => !cache.pinned_sessions.contains(&session),
+                },
+            };
+
+            if !replace {
+                summary.kept += 1;
+                continue;
+            }
+
+            if local.is_some() {
+                summary.replaced += 1;
+            } else {
+                summary.added += 1;
+            }
+            cache.set_session_hue(&session, hue);
+        }
+
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color_cache::FamilySlot;
+    use crate::tmux::fake::pane;
+    use crate::tmux::TmuxPane;
+
+    fn family_cache() -> ColorCache {
+        let mut cache = ColorCache::new();
+        cache.set_session_families(true);
+        cache.session_hues.insert("$1".to_string(), 180.0);
+        let mut cached_color = CachedColor::new(Rgb([60, 160, 160]), 180.0, false);
+        cached_color.session = Some("$1".to_string());
+        cached_color.family = Some(FamilySlot { hue: 180.0, step: 0 });
+        cache.colors.insert("@1:%1".to_string(), cached_color);
+        cache
+    }
+
+    fn session_names() -> HashMap<String, String> {
+        HashMap::from([("$1".to_string(), "infra".to_string())])
+    }
+
+    // The one pane of session "infra"
+    fn infra_pane() -> TmuxPane {
+        TmuxPane {
+            session_id: "$1".to_string(),
+            session_name: "infra".to_string(),
+            ..pane("%1", "@1")
+        }
+    }
+
+    fn exported_hue(hue: f32) -> PortableCache {
+        let mut source = ColorCache::new();
+        source.session_hues.insert("$1".to_string(), hue);
+        let portable = PortableCache::from_cache(&source, true, &session_names());
+        PortableCache::from_json(&portable.to_json().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn session_hues_are_exported_by_name() {
+        let mut cache = ColorCache::new();
+        cache.session_hues.insert("$1".to_string(), 180.0);
+        // A session that is gone, and one imported by name
+        cache.session_hues.insert("$7".to_string(), 90.0);
+        cache.set_session_hue("web", 300.0);
+
+        let portable = PortableCache::from_cache(&cache, false, &session_names());
+        assert_eq!(
+            portable.session_hues,
+            BTreeMap::from([("infra".to_string(), 180.0), ("web".to_string(), 300.0)])
+        );
+    }
+
+    #[test]
+    fn session_hue_is_kept_under_keep() {
+        let mut cache = family_cache();
+
+        let summary = exported_hue(200.0)
+            .merge_into(&mut cache, MergeStrategy::Keep, &session_names())
+            .unwrap();
+
+        assert_eq!(summary.kept, 1);
+        assert_eq!(cache.session_hues["$1"], 180.0);
+        assert!(!cache.session_hues.contains_key("infra"));
+        assert!(cache.colors.contains_key("@1:%1"));
+    }
+
+    #[test]
+    fn imported_session_hue_replaces_family_colors_and_stays() {
+        let mut cache = family_cache();
+
+        let summary = exported_hue(200.0)
+            .merge_into(&mut cache, MergeStrategy::Overwrite, &session_names())
+            .unwrap();
+
+        assert_eq!(summary.replaced, 1);
+        assert_eq!(cache.session_hues["infra"], 200.0);
+
+        // The next render moves the session's family colors to the new hue
+        cache.assign_pane_colors(&[infra_pane()]);
+        assert_eq!(cache.colors["@1:%1"].family.unwrap().hue, 200.0);
+        assert_eq!(cache.session_hues["$1"], 200.0);
+        cache.gc(&[]);
+        cache.reroll_all();
+        assert_eq!(cache.session_hues["infra"], 200.0);
+    }
+}
@@ -0,0 +1,246 @@
+use crate::color::{ansi_swatch, to_hex};
+use crate::color_cache::{unix_now, ColorCache};
+use crate::tmux::TmuxPane;
+use clap::ValueEnum;
+use image::Rgb;
+use serde::Serialize;
+
+const TITLE_WIDTH: usize = 24;
+
+/// Order of the rows of `cache show`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ShowSort {
+    /// Cache key
+    #[default]
+    Key,
+    /// Session, then window and pane index
+    Session,
+    /// Hue around the color wheel
+    Hue,
+    /// Most recently seen first
+    LastSeen,
+    /// Newest first
+    Created,
+}
+
+/// A cached color joined with the live pane it belongs to, if any
+#[derive(Debug, Serialize)]
+pub struct CacheEntry {
+    pub key: String,
+    pub color: String,
+    pub hue: f32,
+    pub pinned: bool,
+    pub created_at: u64,
+    pub last_seen: u64,
+    pub pane: Option<PaneInfo>,
+    #[serde(skip)]
+    rgb: Rgb<u8>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PaneInfo {
+    pub id: String,
+    pub session: String,
+    pub window_index: u32,
+    pub pane_index: u32,
+    pub title: String,
+}
+
+/// One entry per cached color, joined with the given live panes by key
+pub fn cache_entries(cache: &ColorCache, panes: &[TmuxPane]) -> Vec<CacheEntry> {
+    cache
+        .colors
+        .iter()
+        .map(|(key, cached_color)| {
+            let pane = panes
+                .iter()
+                .find(|pane| cache.key_for(pane) == *key)
+                .map(|pane| PaneInfo {
+                    id: pane.id.clone(),
+                    session: pane.session_name.clone(),
+                    window_index: pane.window_index,
+                    pane_index: pane.pane_index,
+                    title: pane.title.clone(),
+                });
+
+            CacheEntry {
+                key: key.clone(),
+                color: to_hex(Rgb(cached_color.rgb)),
+                hue: cached_color.hue,
+                pinned: cached_color.pinned,
+                created_at: cached_color.created_at,
+                last_seen: cached_color.last_seen_at(),
+                pane,
+                rgb: Rgb(cached_color.rgb),
+            }
+        })
+        .collect()
+}
+
+/// Keeps the entries of live panes, pinned colors or panes of one session,
+/// as asked for
+pub fn filter_entries(
+    entries: &mut Vec<CacheEntry>,
+    session: Option<&str>,
+    live: bool,
+    pinned: bool,
+) {
+    entries.retain(|entry| {
+        (!live || entry.pane.is_some())
+            && (!pinned || entry.pinned)
+            && session.is_none_or(|session| {
+                entry
+                    .pane
+                    .as_ref()
+                    .is_some_and(|pane| pane.session == session)
+            })
+    });
+}
+
+pub fn sort_entries(entries: &mut [CacheEntry], sort: ShowSort) {
+    match sort {
+        ShowSort::Key => entries.sort_by(|a, b| a.key.cmp(&b.key)),
+        ShowSort::Session => entries.sort_by(|a, b| {
+            let position = |entry: &CacheEntry| {
+                entry
+                    .pane
+                    .as_ref()
+                    .map(|pane| (pane.session.clone(), pane.window_index, pane.pane_index))
+            };
+            // Live panes first, cached-only entries after them
+            position(a)
+                .is_none()
+                .cmp(&position(b).is_none())
+                .then_with(|| position(a).cmp(&position(b)))
+                .then_with(|| a.key.cmp(&b.key))
+        }),
+        ShowSort::Hue => entries.sort_by(|a, b| a.hue.total_cmp(&b.hue)),
+        ShowSort::LastSeen => entries.sort_by_key(|entry| std::cmp::Reverse(entry.last_seen)),
+        ShowSort::Created => entries.sort_by_key(|entry| std::cmp::Reverse(entry.created_at)),
+    }
+}
+
+/// Prints the entries as a table with a truecolor swatch per row
+pub fn print_table(entries: &[CacheEntry]) {
+    let rows: Vec<(String, String)> = entries
+        .iter()
+        .map(|entry| match &entry.pane {
+            Some(pane) => (
+                format!(
+                    "{}:{}.{} {}",
+                    pane.session, pane.window_index, pane.pane_index, pane.id
+                ),
+                pane.title.chars().take(TITLE_WIDTH).collect(),
+            ),
+            None => ("-".to_string(), String::new()),
+        })
+        .collect();
+
+    let width = |cells: &mut dyn Iterator<Item = usize>, header: &str| {
+        cells.max().unwrap_or(0).max(header.len())
+    };
+    let key_width = width(
+        &mut entries.iter().map(|entry| entry.key.chars().count()),
+        "KEY",
+    );
+    let pane_width = width(
+        &mut rows.iter().map(|(pane, _)| pane.chars().count()),
+        "PANE",
+    );
+    let now = unix_now();
+
+    println!(
+        "       {:<7}  {:<key_width$}  {:<pane_width$}  {:<TITLE_WIDTH$}  {:>9}",
+        "COLOR", "KEY", "PANE", "TITLE", "LAST SEEN"
+    );
+    for (entry, (pane, title)) in entries.iter().zip(rows) {
+        println!(
+            "  {}  {}  {:<key_width$}  {:<pane_width$}  {:<TITLE_WIDTH$}  {:>9}{}",
+            ansi_swatch(entry.rgb, 3),
+            entry.color,
+            entry.key,
+            pane,
+            title,
+            format_age(now.saturating_sub(entry.last_seen)),
+            if entry.pinned { "  [pinned]" } else { "" }
+        );
+    }
+}
+
+fn format_age(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s ago", secs),
+        60..=3599 => format!("{}m ago", secs / 60),
+        3600..=86399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color_cache::CachedColor;
+    use crate::tmux::fake::pane;
+
+    // Two live panes in sessions "work" and "main", and a vanished one
+    fn entries() -> Vec<CacheEntry> {
+        let mut cache = ColorCache::new();
+        for (key, hue, pinned, created_at, last_seen) in [
+            ("@1:%1", 200.0, false, 100, 400),
+            ("@1:%2", 20.0, true, 300, 300),
+            ("@2:%9", 100.0, false, 200, 200),
+        ] {
+            let mut cached_color = CachedColor::new(Rgb([128, 128, 128]), hue, pinned);
+            cached_color.created_at = created_at;
+            cached_color.last_seen = last_seen;
+            cache.colors.insert(key.to_string(), cached_color);
+        }
+        let panes = [
+            TmuxPane {
+                session_name: "work".to_string(),
+                pane_index: 1,
+                ..pane("%1", "@1")
+            },
+            pane("%2", "@1"),
+        ];
+        cache_entries(&cache, &panes)
+    }
+
+    fn keys(entries: &[CacheEntry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.key.as_str()).collect()
+    }
+
+    #[test]
+    fn sort_orders() {
+        let cases = [
+            (ShowSort::Key, ["@1:%1", "@1:%2", "@2:%9"]),
+            // Live panes by session, the vanished one last
+            (ShowSort::Session, ["@1:%2", "@1:%1", "@2:%9"]),
+            (ShowSort::Hue, ["@1:%2", "@2:%9", "@1:%1"]),
+            (ShowSort::LastSeen, ["@1:%1", "@1:%2", "@2:%9"]),
+            (ShowSort::Created, ["@1:%2", "@2:%9", "@1:%1"]),
+        ];
+        for (sort, expected) in cases {
+            let mut entries = entries();
+            sort_entries(&mut entries, sort);
+            assert_eq!(keys(&entries), expected, "{:?}", sort);
+        }
+    }
+
+    #[test]
+    fn filters() {
+        let filtered = |session: Option<&str>, live: bool, pinned: bool| {
+            let mut entries = entries();
+            filter_entries(&mut entries, session, live, pinned);
+            sort_entries(&mut entries, ShowSort::Key);
+            keys(&entries).join(" ")
+        };
+
+        assert_eq!(filtered(None, false, false), "@1:%1 @1:%2 @2:%9");
+        assert_eq!(filtered(Some("work"), false, false), "@1:%1");
+        assert_eq!(filtered(Some("gone"), false, false), "");
+        assert_eq!(filtered(None, true, false), "@1:%1 @1:%2");
+        assert_eq!(filtered(None, false, true), "@1:%2");
+        assert_eq!(filtered(Some("work"), false, true), "");
+    }
+}
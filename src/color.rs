@@ -0,0 +1,478 @@
+use clap::ValueEnum;
+use image::Rgb;
+use serde::{Deserialize, Serialize};
+
+/// A color in the OKLCH space: perceptual lightness (0-1), chroma and hue in degrees
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Oklch {
+    pub l: f32,
+    pub c: f32,
+    pub h: f32,
+}
+
+/// A color in CIE L*a*b* (D65), used for CIEDE2000 distances
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Lab {
+    pub l: f32,
+    pub a: f32,
+    pub b: f32,
+}
+
+impl Oklch {
+    pub fn new(l: f32, c: f32, h: f32) -> Self {
+        Self {
+            l,
+            c,
+            h: h.rem_euclid(360.0),
+        }
+    }
+
+    pub fn from_rgb(rgb: Rgb<u8>) -> Self {
+        let [r, g, b] = rgb.0.map(|v| srgb_to_linear(v as f32 / 255.0));
+        let (l, a, b) = linear_srgb_to_oklab(r, g, b);
+        Self::new(l, (a * a + b * b).sqrt(), b.atan2(a).to_degrees())
+    }
+
+    /// Converts to sRGB, reducing chroma until the color fits in the gamut
+    /// so that lightness and hue are preserved
+    pub fn to_rgb(self) -> Rgb<u8> {
+        if let Some(rgb) = self.to_linear_in_gamut() {
+            return encode_srgb(rgb);
+        }
+
+        let mut low = 0.0f32;
+        let mut high = self.c;
+        for _ in 0..20 {
+            let mid = (low + high) / 2.0;
+            if (Self { c: mid, ..self }).to_linear_in_gamut().is_some() {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+
+        let mapped = Self { c: low, ..self };
+        encode_srgb(mapped.to_linear_unclamped())
+    }
+
+    fn to_linear_unclamped(self) -> [f32; 3] {
+        let h = self.h.to_radians();
+        oklab_to_linear_srgb(self.l, self.c * h.cos(), self.c * h.sin())
+    }
+
+    fn to_linear_in_gamut(self) -> Option<[f32; 3]> {
+        const EPSILON: f32 = 1e-4;
+        let rgb = self.to_linear_unclamped();
+        rgb.iter()
+            .all(|&v| (-EPSILON..=1.0 + EPSILON).contains(&v))
+            .then_some(rgb)
+    }
+}
+
+impl Lab {
+    pub fn from_rgb(rgb: Rgb<u8>) -> Self {
+        let [r, g, b] = rgb.0.map(|v| srgb_to_linear(v as f32 / 255.0));
+
+        // Linear sRGB -> XYZ (D65), normalised by the reference white
+        let x = (0.412_456_4 * r + 0.357_576_1 * g + 0.180_437_5 * b) / 0.950_47;
+        let y = 0.212_672_9 * r + 0.715_152_2 * g + 0.072_175 * b;
+        let z = (0.019_333_9 * r + 0.119_192 * g + 0.950_304_1 * b) / 1.088_83;
+
+        let f = |t: f32| {
+            if t > 216.0 / 24389.0 {
+                t.cbrt()
+            } else {
+                (24389.0 / 27.0 * t + 16.0) / 116.0
+            }
+        };
+        let (fx, fy, fz) = (f(x), f(y), f(z));
+
+        Self {
+            l: 116.0 * fy - 16.0,
+            a: 500.0 * (fx - fy),
+            b: 200.0 * (fy - fz),
+        }
+    }
+}
+
+/// Perceptual color difference between two colors (CIEDE2000, ΔE00).
+/// Values below ~2 are hard to tell apart; 20+ is clearly different.
+pub fn delta_e(rgb1: Rgb<u8>, rgb2: Rgb<u8>) -> f32 {
+    ciede2000(Lab::from_rgb(rgb1), Lab::from_rgb(rgb2))
+}
+
+pub fn ciede2000(lab1: Lab, lab2: Lab) -> f32 {
+    let pow7 = |v: f32| v.powi(7);
+    let twenty_five_pow7 = pow7(25.0);
+
+    let c1 = (lab1.a * lab1.a + lab1.b * lab1.b).sqrt();
+    let c2 = (lab2.a * lab2.a + lab2.b * lab2.b).sqrt();
+    let c_bar = (c1 + c2) / 2.0;
+    let g = 0.5 * (1.0 - (pow7(c_bar) / (pow7(c_bar) + twenty_five_pow7)).sqrt());
+
+    let a1 = (1.0 + g) * lab1.a;
+    let a2 = (1.0 + g) * lab2.a;
+    let c1 = (a1 * a1 + lab1.b * lab1.b).sqrt();
+    let c2 = (a2 * a2 + lab2.b * lab2.b).sqrt();
+
+    let hue = |b: f32, a: f32| {
+        if a == 0.0 && b == 0.0 {
+            0.0
+        } else {
+            b.atan2(a).to_degrees().rem_euclid(360.0)
+        }
+    };
+    let h1 = hue(lab1.b, a1);
+    let h2 = hue(lab2.b, a2);
+
+    let delta_l = lab2.l - lab1.l;
+    let delta_c = c2 - c1;
+    let delta_h = if c1 * c2 == 0.0 {
+        0.0
+    } else if (h2 - h1).abs() <= 180.0 {
+        h2 - h1
+    } else if h2 <= h1 {
+        h2 - h1 + 360.0
+    } else {
+        h2 - h1 - 360.0
+    };
+    let delta_big_h = 2.0 * (c1 * c2).sqrt() * (delta_h.to_radians() / 2.0).sin();
+
+    let l_bar = (lab1.l + lab2.l) / 2.0;
+    let c_bar = (c1 + c2) / 2.0;
+    let h_bar = if c1 * c2 == 0.0 {
+        h1 + h2
+    } else if (h1 - h2).abs() <= 180.0 {
+        (h1 + h2) / 2.0
+    } else if h1 + h2 < 360.0 {
+        (h1 + h2 + 360.0) / 2.0
+    } else {
+        (h1 + h2 - 360.0) / 2.0
+    };
+
+    let t = 1.0 - 0.17 * (h_bar - 30.0).to_radians().cos()
+        + 0.24 * (2.0 * h_bar).to_radians().cos()
+        + 0.32 * (3.0 * h_bar + 6.0).to_radians().cos()
+        - 0.20 * (4.0 * h_bar - 63.0).to_radians().cos();
+
+    let delta_theta = 30.0 * (-((h_bar - 275.0) / 25.0).powi(2)).exp();
+    let r_c = 2.0 * (pow7(c_bar) / (pow7(c_bar) + twenty_five_pow7)).sqrt();
+    let s_l = 1.0 + (0.015 * (l_bar - 50.0).powi(2)) / (20.0 + (l_bar - 50.0).powi(2)).sqrt();
+    let s_c = 1.0 + 0.045 * c_bar;
+    let s_h = 1.0 + 0.015 * c_bar * t;
+    let r_t = -(2.0 * delta_theta).to_radians().sin() * r_c;
+
+    let term_l = delta_l / s_l;
+    let term_c = delta_c / s_c;
+    let term_h = delta_big_h / s_h;
+
+    (term_l * term_l + term_c * term_c + term_h * term_h + r_t * term_c * term_h).sqrt()
+}
+
+fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.040_45 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.003_130_8 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn encode_srgb(rgb: [f32; 3]) -> Rgb<u8> {
+    Rgb(rgb.map(|v| (linear_to_srgb(v.clamp(0.0, 1.0)) * 255.0).round() as u8))
+}
+
+fn linear_srgb_to_oklab(r: f32, g: f32, b: f32) -> (f32, f32, f32) {
+    let l = (0.412_221_46 * r + 0.536_332_55 * g + 0.051_445_995 * b).cbrt();
+    let m = (0.211_903_5 * r + 0.680_699_5 * g + 0.107_396_96 * b).cbrt();
+    let s = (0.088_302_46 * r + 0.281_718_85 * g + 0.629_978_7 * b).cbrt();
+
+    (
+        0.210_454_26 * l + 0.793_617_8 * m - 0.004_072_047 * s,
+        1.977_998_5 * l - 2.428_592_2 * m + 0.450_593_7 * s,
+        0.025_904_037 * l + 0.782_771_77 * m - 0.808_675_77 * s,
+    )
+}
+
+fn oklab_to_linear_srgb(l: f32, a: f32, b: f32) -> [f32; 3] {
+    let l_ = l + 0.396_337_78 * a + 0.215_803_76 * b;
+    let m_ = l - 0.105_561_346 * a - 0.063_854_17 * b;
+    let s_ = l - 0.089_484_18 * a - 1.291_485_5 * b;
+
+    let (l, m, s) = (l_ * l_ * l_, m_ * m_ * m_, s_ * s_ * s_);
+
+    [
+        4.076_741_7 * l - 3.307_711_6 * m + 0.230_969_94 * s,
+        -1.268_438 * l + 2.609_757_4 * m - 0.341_319_38 * s,
+        -0.004_196_086_3 * l - 0.703_418_6 * m + 1.707_614_7 * s,
+    ]
+}
+
+/// WCAG relative luminance of an sRGB color
+pub fn relative_luminance(rgb: Rgb<u8>) -> f32 {
+    let [r, g, b] = rgb.0.map(|v| srgb_to_linear(v as f32 / 255.0));
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+/// WCAG contrast ratio between two colors, from 1.0 (identical) to 21.0
+pub fn contrast_ratio(rgb1: Rgb<u8>, rgb2: Rgb<u8>) -> f32 {
+    let l1 = relative_luminance(rgb1);
+    let l2 = relative_luminance(rgb2);
+    (l1.max(l2) + 0.05) / (l1.min(l2) + 0.05)
+}
+
+/// Text colors a pane background has to stay readable against
+#[derive(Debug, Clone)]
+pub struct TextContrast {
+    pub text_colors: Vec<Rgb<u8>>,
+    pub min_ratio: f32,
+    /// Whether backgrounds get darker (light text) or lighter (dark text) to gain contrast
+    pub darken: bool,
+}
+
+impl TextContrast {
+    /// Builds the requirement from the terminal's foreground, its ANSI palette
+    /// and its own background. ANSI colors that are not readable on the theme's
+    /// own background are ignored, since no pane color could rescue them.
+    pub fn new(foreground: Rgb<u8>, background: Rgb<u8>, ansi: &[Rgb<u8>], min_ratio: f32) -> Self {
+        let mut text_colors = vec![foreground];
+        text_colors.extend(
+            ansi.iter()
+                .copied()
+                .filter(|&color| contrast_ratio(color, background) >= min_ratio),
+        );
+
+        Self {
+            text_colors,
+            min_ratio,
+            darken: relative_luminance(foreground) > relative_luminance(background),
+        }
+    }
+
+    fn min_contrast(&self, background: Rgb<u8>) -> f32 {
+        self.text_colors
+            .iter()
+            .map(|&text| contrast_ratio(text, background))
+            .fold(f32::MAX, f32::min)
+    }
+
+    /// Moves the color's lightness away from the text colors until every one
+    /// of them meets the minimum contrast ratio. Hue is preserved; if the
+    /// ratio cannot be reached the most readable lightness is used.
+    pub fn ensure(&self, color: Oklch) -> Rgb<u8> {
+        const STEP: f32 = 0.01;
+
+        let mut best = color.to_rgb();
+        let mut best_contrast = self.min_contrast(best);
+        let mut lightness = color.l;
+
+        while best_contrast < self.min_ratio && (0.0..=1.0).contains(&lightness) {
+            lightness += if self.darken { -STEP } else { STEP };
+            let candidate = Oklch {
+                l: lightness.clamp(0.0, 1.0),
+                ..color
+            }
+            .to_rgb();
+            let contrast = self.min_contrast(candidate);
+            if contrast > best_contrast {
+                best = candidate;
+                best_contrast = contrast;
+            }
+        }
+
+        best
+    }
+}
+
+/// Color vision deficiencies that pane colors can be kept distinguishable for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorVision {
+    Protanopia,
+    Deuteranopia,
+    Tritanopia,
+}
+
+impl ColorVision {
+    /// Simulates how the color looks with this deficiency, using the
+    /// full-severity matrices of Machado, Oliveira and Fernandes (2009)
+    pub fn simulate(self, rgb: Rgb<u8>) -> Rgb<u8> {
+        let matrix: [[f32; 3]; 3] = match self {
+            ColorVision::Protanopia => [
+                [0.152_286, 1.052_583, -0.204_868],
+                [0.114_503, 0.786_281, 0.099_216],
+                [-0.003_882, -0.048_116, 1.051_998],
+            ],
+            ColorVision::Deuteranopia => [
+                [0.367_322, 0.860_646, -0.227_968],
+                [0.280_085, 0.672_501, 0.047_413],
+                [-0.011_820, 0.042_940, 0.968_881],
+            ],
+            ColorVision::Tritanopia => [
+                [1.255_528, -0.076_749, -0.178_779],
+                [-0.078_411, 0.930_809, 0.147_602],
+                [0.004_733, 0.691_367, 0.303_900],
+            ],
+        };
+
+        let linear = rgb.0.map(|v| srgb_to_linear(v as f32 / 255.0));
+        encode_srgb(matrix.map(|row| row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2]))
+    }
+}
+
+/// A block of spaces painted with a 24-bit ANSI background color
+pub fn ansi_swatch(rgb: Rgb<u8>, width: usize) -> String {
+    let [r, g, b] = rgb.0;
+    format!("\x1b[48;2;{};{};{}m{}\x1b[0m", r, g, b, " ".repeat(width))
+}
+
+/// Looks up a few common color names (case-insensitive)
+pub fn named_color(name: &str) -> Option<Rgb<u8>> {
+    let rgb = match name.to_ascii_lowercase().as_str() {
+        "black" => [0, 0, 0],
+        "white" => [255, 255, 255],
+        "gray" | "grey" => [128, 128, 128],
+        "red" => [220, 50, 47],
+        "orange" => [255, 165, 0],
+        "yellow" => [255, 215, 0],
+        "green" => [60, 179, 113],
+        "teal" => [0, 128, 128],
+        "cyan" => [0, 206, 209],
+        "blue" => [100, 149, 237],
+        "navy" => [0, 0, 128],
+        "purple" => [147, 112, 219],
+        "magenta" => [255, 0, 255],
+        "pink" => [255, 182, 193],
+        "brown" => [165, 42, 42],
+        "tomato" => [255, 99, 71],
+        "gold" => [255, 215, 0],
+        "lavender" => [230, 230, 250],
+        "mint" => [189, 252, 201],
+        "peach" => [255, 218, 185],
+        "coral" => [255, 127, 80],
+        "olive" => [128, 128, 0],
+        _ => return None,
+    };
+    Some(Rgb(rgb))
+}
+
+/// Formats a color as `#rrggbb`
+pub fn to_hex(rgb: Rgb<u8>) -> String {
+    format!("#{:02x}{:02x}{:02x}", rgb[0], rgb[1], rgb[2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ciede2000_matches_the_sharma_reference_pairs() {
+        // Sharma, Wu and Dalal (2005), Table 1: L*a*b* pairs and their ΔE00
+        let pairs: [([f32; 3], [f32; 3], f32); 34] = [
+            ([50.0, 2.6772, -79.7751], [50.0, 0.0, -82.7485], 2.0425),
+            ([50.0, 3.1571, -77.2803], [50.0, 0.0, -82.7485], 2.8615),
+            ([50.0, 2.8361, -74.0200], [50.0, 0.0, -82.7485], 3.4412),
+            ([50.0, -1.3802, -84.2814], [50.0, 0.0, -82.7485], 1.0000),
+            ([50.0, -1.1848, -84.8006], [50.0, 0.0, -82.7485], 1.0000),
+            ([50.0, -0.9009, -85.5211], [50.0, 0.0, -82.7485], 1.0000),
+            ([50.0, 0.0, 0.0], [50.0, -1.0, 2.0], 2.3669),
+            ([50.0, -1.0, 2.0], [50.0, 0.0, 0.0], 2.3669),
+            ([50.0, 2.4900, -0.0010], [50.0, -2.4900, 0.0009], 7.1792),
+            ([50.0, 2.4900, -0.0010], [50.0, -2.4900, 0.0010], 7.1792),
+            ([50.0, 2.4900, -0.0010], [50.0, -2.4900, 0.0011], 7.2195),
+            ([50.0, 2.4900, -0.0010], [50.0, -2.4900, 0.0012], 7.2195),
+            ([50.0, -0.0010, 2.4900], [50.0, 0.0009, -2.4900], 4.8045),
+            ([50.0, -0.0010, 2.4900], [50.0, 0.0010, -2.4900], 4.8045),
+            ([50.0, -0.0010, 2.4900], [50.0, 0.0011, -2.4900], 4.7461),
+            ([50.0, 2.5, 0.0], [50.0, 0.0, -2.5], 4.3065),
+            ([50.0, 2.5, 0.0], [73.0, 25.0, -18.0], 27.1492),
+            ([50.0, 2.5, 0.0], [61.0, -5.0, 29.0], 22.8977),
+            ([50.0, 2.5, 0.0], [56.0, -27.0, -3.0], 31.9030),
+            ([50.0, 2.5, 0.0], [58.0, 24.0, 15.0], 19.4535),
+            ([50.0, 2.5, 0.0], [50.0, 3.1736, 0.5854], 1.0000),
+            ([50.0, 2.5, 0.0], [50.0, 3.2972, 0.0], 1.0000),
+            ([50.0, 2.5, 0.0], [50.0, 1.8634, 0.5757], 1.0000),
+            ([50.0, 2.5, 0.0], [50.0, 3.2592, 0.3350], 1.0000),
+            ([60.2574, -34.0099, 36.2677], [60.4626, -34.1751, 39.4387], 1.2644),
+            ([63.0109, -31.0961, -5.8663], [62.8187, -29.7946, -4.0864], 1.2630),
+            ([61.2901, 3.7196, -5.3901], [61.4292, 2.2480, -4.9620], 1.8731),
+            ([35.0831, -44.1164, 3.7933], [35.0232, -40.0716, 1.5901], 1.8645),
+            ([22.7233, 20.0904, -46.6940], [23.0331, 14.9730, -42.5619], 2.0373),
+            ([36.4612, 47.8580, 18.3852], [36.2715, 50.5065, 21.2231], 1.4146),
+            ([90.8027, -2.0831, 1.4410], [91.1528, -1.6435, 0.0447], 1.4441),
+            ([90.9257, -0.5406, -0.9208], [88.6381, -0.8985, -0.7239], 1.5381),
+            ([6.7747, -0.2908, -2.4247], [5.8714, -0.0985, -2.2286], 0.6377),
+            ([2.0776, 0.0795, -1.1350], [0.9033, -0.0636, -0.5514], 0.9082),
+        ];
+
+        for (i, ([l1, a1, b1], [l2, a2, b2], expected)) in pairs.into_iter().enumerate() {
+            let lab1 = Lab { l: l1, a: a1, b: b1 };
+            let lab2 = Lab { l: l2, a: a2, b: b2 };
+            let delta = ciede2000(lab1, lab2);
+            assert!((delta - expected).abs() < 1e-3, "pair {}: {} != {}", i + 1, delta, expected);
+            // The difference is symmetric
+            assert!((ciede2000(lab2, lab1) - delta).abs() < 1e-3, "pair {} reversed", i + 1);
+        }
+    }
+
+    #[test]
+    fn black_on_white_is_the_maximum_contrast() {
+        let black = Rgb([0, 0, 0]);
+        let white = Rgb([255, 255, 255]);
+        assert!((contrast_ratio(black, white) - 21.0).abs() < 1e-4);
+        assert!((contrast_ratio(white, black) - 21.0).abs() < 1e-4);
+        assert!((contrast_ratio(white, white) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn contrast_direction_flips_with_the_theme() {
+        let light = Rgb([220, 220, 220]);
+        let dark = Rgb([30, 30, 30]);
+        let pastel = Oklch::new(0.8, 0.08, 100.0);
+
+        // Light text on a dark theme: backgrounds get darker
+        let dark_theme = TextContrast::new(light, dark, &[], 4.5);
+        assert!(dark_theme.darken);
+        let background = dark_theme.ensure(pastel);
+        assert!(contrast_ratio(light, background) >= 4.5);
+        assert!(relative_luminance(background) < relative_luminance(pastel.to_rgb()));
+
+        // Dark text on a light theme: backgrounds get lighter
+        let light_theme = TextContrast::new(dark, light, &[], 4.5);
+        assert!(!light_theme.darken);
+        let background = light_theme.ensure(Oklch::new(0.4, 0.08, 100.0));
+        assert!(contrast_ratio(dark, background) >= 4.5);
+    }
+
+    #[test]
+    fn unreadable_ansi_colors_are_ignored() {
+        let foreground = Rgb([220, 220, 220]);
+        let background = Rgb([30, 30, 30]);
+        let yellow = Rgb([240, 220, 80]);
+        let navy = Rgb([0, 0, 90]);
+
+        let contrast = TextContrast::new(foreground, background, &[yellow, navy], 4.5);
+        assert_eq!(contrast.text_colors, [foreground, yellow]);
+    }
+
+    #[test]
+    fn simulation_matches_the_machado_matrices() {
+        let cases = [
+            (ColorVision::Protanopia, Rgb([255, 0, 0]), Rgb([109, 95, 0])),
+            (ColorVision::Deuteranopia, Rgb([0, 255, 0]), Rgb([239, 214, 58])),
+            (ColorVision::Tritanopia, Rgb([0, 0, 255]), Rgb([0, 107, 150])),
+        ];
+        for (color_vision, rgb, expected) in cases {
+            assert_eq!(color_vision.simulate(rgb), expected, "{:?}", color_vision);
+            // Neutral colors look the same to everyone
+            assert_eq!(color_vision.simulate(Rgb([255, 255, 255])), Rgb([255, 255, 255]));
+        }
+    }
+}
@@ -1,230 +1,2356 @@
+use crate::adjacency::pane_neighbors;
+use crate::color::{delta_e, ColorVision, Oklch, TextContrast};
+use crate::color_key::KeyStrategy;
+use crate::harmony::Harmony;
+use crate::layout::{parse_window_layout, LayoutCell};
+use crate::palette::Palette;
+use crate::schedule::ColorShift;
+use crate::tmux::TmuxPane;
 use anyhow::{Result, Context};
 use image::Rgb;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Once, OnceLock};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Version of the on-disk cache format. Files without a version were written
+/// before it was introduced and are treated as version 1.
+const CACHE_VERSION: u32 = 2;
+
+// Socket path of the tmux server whose cache this process uses
+static SERVER_SOCKET: OnceLock<Option<String>> = OnceLock::new();
+
+/// Selects the cache of the tmux server at `socket_path`. Servers get
+/// separate caches since their pane and window ids overlap.
+pub fn set_server(socket_path: Option<String>) {
+    let _ = SERVER_SOCKET.set(socket_path);
+}
+
+fn current_server() -> Option<String> {
+    SERVER_SOCKET.get().cloned().flatten()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct CachedColor {
     pub rgb: [u8; 3],
-    pub hue: f32, // Store hue for better distinctness
+    pub hue: f32, // OKLCH hue, kept for reference
     pub created_at: u64, // timestamp
+    /// When the pane was last part of a render
+    #[serde(default)]
+    pub last_seen: u64,
+    /// Pinned colors are never regenerated, recolored or rerolled
+    #[serde(default)]
+    pub pinned: bool,
+    /// tmux session id of the pane, whose base hue the color follows under
+    /// a harmony
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    /// Where the color sits in its split-tree or session family
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub family: Option<FamilySlot>,
+}
+
+/// A family color's base hue and lightness step, kept so siblings never
+/// reuse a step whatever the tone or text contrast did to the color
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct FamilySlot {
+    pub hue: f32,
+    pub step: u32,
+}
+
+impl CachedColor {
+    pub fn new(color: Rgb<u8>, hue: f32, pinned: bool) -> Self {
+        let now = unix_now();
+        Self {
+            rgb: color.0,
+            hue,
+            created_at: now,
+            last_seen: now,
+            pinned,
+            session: None,
+            family: None,
+        }
+    }
+
+    /// Whether both are the same color, ignoring when it was last seen
+    fn same_color(&self, other: &Self) -> bool {
+        Self {
+            last_seen: other.last_seen,
+            ..self.clone()
+        } == *other
+    }
+
+    // Caches written before last_seen existed only know the creation time
+    pub fn last_seen_at(&self) -> u64 {
+        self.last_seen.max(self.created_at)
+    }
+}
+
+/// Which colors go first when the cache is over its size limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Eviction {
+    /// Colors whose panes were seen least recently
+    #[default]
+    LeastRecentlyUsed,
+    /// Colors that were created first
+    Oldest,
+}
+
+/// How long colors of vanished panes are kept and how many are kept at most
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetentionPolicy {
+    /// Colors of panes that no longer exist, pinned ones included, are kept
+    /// this long, so a respawned pane gets its color back
+    pub grace_period_secs: u64,
+    /// Upper bound on cached colors; live and pinned colors are exempt
+    pub max_entries: usize,
+    pub evict_by: Eviction,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            grace_period_secs: 7 * 24 * 60 * 60,
+            max_entries: 256,
+            evict_by: Eviction::default(),
+        }
+    }
+}
+
+/// Settings of the pass that re-spreads visible hues around the wheel
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Rebalance {
+    /// How strongly existing hues resist moving, from 0 (spread them evenly)
+    /// to 1 (leave them where they are)
+    pub stability: f32,
+}
+
+impl Default for Rebalance {
+    fn default() -> Self {
+        Self { stability: 0.5 }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ColorCache {
+    /// Format version, see [`CACHE_VERSION`]
+    #[serde(default)]
+    pub version: u32,
+    /// Socket path of the tmux server the colors belong to
+    #[serde(default)]
+    pub server: Option<String>,
     pub colors: HashMap<String, CachedColor>,
     pub startup_seed: u64,
-    pub used_hues: Vec<f32>, // Track used hues for better distribution
+    /// Tone the cached colors were generated with
+    #[serde(default)]
+    pub tone: PaneTone,
+    /// Name of the palette the cached colors were picked from, if any
+    #[serde(default)]
+    pub palette: Option<String>,
+    /// Color vision deficiency the cached colors were kept distinct for
+    #[serde(default)]
+    pub color_vision: Option<ColorVision>,
+    /// How the keys in `colors` were derived from panes
+    #[serde(default)]
+    pub key_strategy: KeyStrategy,
+    /// Scheme each session's colors were drawn from, if any
+    #[serde(default)]
+    pub harmony: Option<Harmony>,
+    /// Whether colors follow the windows' split trees
+    #[serde(default)]
+    pub split_tree: bool,
+    /// Whether each session's panes form one family around a session hue
+    #[serde(default)]
+    pub session_families: bool,
+    /// Base hue of every session seen with session families, by session id
+    /// so a renamed session keeps it. Imported hues go by session name.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub session_hues: HashMap<String, f32>,
+    /// Names of the sessions whose hue was imported; it outlives their panes
+    /// and rerolls
+    #[serde(default, skip_serializing_if = "HashSet::is_empty")]
+    pub pinned_sessions: HashSet<String>,
+    /// Visible panes at the last rebalance, which only runs when they change
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    balanced_keys: Vec<String>,
+    #[serde(skip)]
+    text_contrast: Option<TextContrast>,
+    #[serde(skip)]
+    active_palette: Option<Palette>,
+    #[serde(skip)]
+    retention: RetentionPolicy,
+    /// Applied when drawing, never to the cached colors themselves
+    #[serde(skip)]
+    color_shift: ColorShift,
+    /// Colors as they were on disk when loaded, to merge concurrent saves
+    #[serde(skip)]
+    loaded_colors: HashMap<String, CachedColor>,
+    #[serde(skip)]
+    loaded_session_hues: HashMap<String, f32>,
+    #[serde(skip)]
+    loaded_generation: Option<Generation>,
+    /// Panes that still exist, once known; colors kept for vanished panes
+    /// don't crowd out new ones
+    #[serde(skip)]
+    live_keys: Option<HashSet<String>>,
+    /// Pane being rerolled and the family step it must move off
+    #[serde(skip)]
+    rerolled_step: Option<(String, u32)>,
+    /// Whether the seed was fixed for this run, in which case panes are
+    /// colored in key order so the outcome does not depend on timing
+    #[serde(skip)]
+    seeded: bool,
+    /// Stands in for a cache written by a newer version, which is never
+    /// overwritten
+    #[serde(skip)]
+    read_only: bool,
+}
+
+/// Settings cached colors are generated under
+#[derive(Debug, Clone, PartialEq)]
+struct Generation {
+    startup_seed: u64,
+    tone: PaneTone,
+    palette: Option<String>,
+    color_vision: Option<ColorVision>,
+    key_strategy: KeyStrategy,
+    harmony: Option<Harmony>,
+    split_tree: bool,
+    session_families: bool,
+}
+
+/// OKLCH lightness and chroma shared by every pane color, so panes differ
+/// only in hue and none of them looks brighter than the others
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PaneTone {
+    pub lightness: f32,
+    pub chroma: f32,
+}
+
+impl Default for PaneTone {
+    // Bright pastels, used when the terminal theme is unknown
+    fn default() -> Self {
+        Self {
+            lightness: 0.86,
+            chroma: 0.09,
+        }
+    }
+}
+
+impl PaneTone {
+    pub fn color(&self, hue: f32) -> Oklch {
+        Oklch::new(self.lightness, self.chroma, hue)
+    }
 }
 
+// Minimum CIEDE2000 difference for a preferred hue to be accepted as-is
+const MIN_DELTA_E: f32 = 15.0;
+// Differences to panes sharing an edge count for half, so touching panes get
+// twice the minimum difference before far-apart ones are considered
+const NEIGHBOR_WEIGHT: f32 = 0.5;
+// OKLCH lightness between panes of one family, and how far from the tone
+// its steps may go before the family turns to the next hue
+const FAMILY_LIGHTNESS_STEP: f32 = 0.05;
+const FAMILY_LIGHTNESS_RANGE: f32 = 0.2;
+// Hue turn of a family's colors each time its lightness steps run out
+const FAMILY_WRAP_HUE: f32 = 40.0;
+// Smallest difference between two colors of one family, and the step after
+// which a family takes whatever color comes
+const FAMILY_MIN_DELTA_E: f32 = 3.0;
+const FAMILY_MAX_STEPS: u32 = 64;
+// Hue turn of a split nested in another
+const SPLIT_HUE_STEP: f32 = 12.0;
+// Chroma of a session family's windows relative to the tone, by window index
+const WINDOW_SATURATION_STEPS: [f32; 5] = [1.0, 0.55, 1.45, 0.75, 1.25];
+// Session hues need twice the minimum difference, since each is stretched
+// over a whole family of shades
+const SESSION_WEIGHT: f32 = 0.5;
+
 impl ColorCache {
     pub fn new() -> Self {
         let mut rng = rand::thread_rng();
         Self {
+            version: CACHE_VERSION,
+            server: current_server(),
             colors: HashMap::new(),
             startup_seed: rng.gen(),
-            used_hues: Vec::new(),
+            tone: PaneTone::default(),
+            palette: None,
+            color_vision: None,
+            key_strategy: KeyStrategy::default(),
+            harmony: None,
+            split_tree: false,
+            session_families: false,
+            session_hues: HashMap::new(),
+            pinned_sessions: HashSet::new(),
+            balanced_keys: Vec::new(),
+            text_contrast: None,
+            active_palette: None,
+            retention: RetentionPolicy::default(),
+            color_shift: ColorShift::default(),
+            loaded_colors: HashMap::new(),
+            loaded_session_hues: HashMap::new(),
+            loaded_generation: None,
+            live_keys: None,
+            rerolled_step: None,
+            seeded: false,
+            read_only: false,
         }
     }
 
-    pub fn get_cache_path() -> PathBuf {
-        let cache_dir = dirs::cache_dir()
+    fn get_cache_dir() -> PathBuf {
+        dirs::cache_dir()
             .unwrap_or_else(|| PathBuf::from("/tmp"))
-            .join("kitty-pane-bg");
+            .join("kitty-pane-bg")
+    }
+
+    fn get_servers_dir() -> PathBuf {
+        Self::get_cache_dir().join("servers")
+    }
+
+    /// Cache file of the current tmux server
+    pub fn get_cache_path() -> PathBuf {
+        let servers_dir = Self::get_servers_dir();
         
-        if !cache_dir.exists() {
-            let _ = fs::create_dir_all(&cache_dir);
+        if !servers_dir.exists() {
+            let _ = fs::create_dir_all(&servers_dir);
         }
         
-        cache_dir.join("pane_colors.json")
+        servers_dir.join(format!("{}.json", server_namespace(current_server().as_deref())))
     }
 
     pub fn load() -> Result<Self> {
         let cache_path = Self::get_cache_path();
-        
-        if !cache_path.exists() {
-            return Ok(Self::new());
+
+        // Caches used to be shared by every server; the first server to run
+        // adopts the old file
+        let legacy_path = Self::get_cache_dir().join("pane_colors.json");
+        if current_server().is_some() && !cache_path.exists() {
+            let legacy = Self::read_from(&legacy_path)?.filter(|legacy| !legacy.read_only);
+            if let Some(mut legacy) = legacy {
+                legacy.server = current_server();
+                legacy.save().context("Failed to save adopted color cache")?;
+                let _ = fs::remove_file(&legacy_path);
+            }
         }
 
-        let content = fs::read_to_string(&cache_path)
-            .context("Failed to read color cache file")?;
-        
-        let mut cache: Self = serde_json::from_str(&content)
-            .context("Failed to parse color cache")?;
-        
-        // Rebuild used_hues from existing colors
-        cache.used_hues = cache.colors.values().map(|c| c.hue).collect();
-        
+        let mut cache = Self::read_from(&cache_path)?.unwrap_or_else(Self::new);
+        cache.server = current_server();
+        cache.mark_loaded();
+        Ok(cache)
+    }
+
+    /// Loads the cache for commands that only look at it: a legacy cache is
+    /// read in place rather than adopted, so nothing is written
+    pub fn load_unsaved() -> Result<Self> {
+        let cache_path = Self::get_cache_path();
+        let legacy_path = Self::get_cache_dir().join("pane_colors.json");
+
+        let on_disk = match Self::read_from(&cache_path)? {
+            Some(cache) => Some(cache),
+            None if current_server().is_some() => Self::read_from(&legacy_path)?,
+            None => None,
+        };
+        let mut cache = on_disk.unwrap_or_else(Self::new);
+        cache.server = current_server();
         Ok(cache)
     }
 
+    /// Caches of every tmux server seen so far, sorted by file name
+    pub fn server_caches() -> Vec<ServerCache> {
+        let Ok(entries) = fs::read_dir(Self::get_servers_dir()) else {
+            return Vec::new();
+        };
+
+        let mut caches: Vec<ServerCache> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|path| {
+                let cache = fs::read_to_string(&path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|content| Self::parse(&content));
+                match cache {
+                    Ok(cache) => Some(ServerCache {
+                        path,
+                        server: cache.server,
+                        colors: cache.colors.len(),
+                    }),
+                    Err(e) => {
+                        eprintln!("Warning: Skipping color cache {}: {:#}", path.display(), e);
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        caches.sort_by(|a, b| a.path.cmp(&b.path));
+        caches
+    }
+
+    // A file that cannot be read or parsed is moved aside with a warning and
+    // treated as missing, so a bad cache costs the colors but never breaks
+    // renders. A file written by a newer version is left alone, since that
+    // version can still read it: renders use a fresh cache that is never
+    // saved.
+    fn read_from(cache_path: &Path) -> Result<Option<Self>> {
+        if !cache_path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read(cache_path)
+            .context("Failed to read color cache file")
+            .and_then(|bytes| String::from_utf8(bytes).context("Color cache is not valid UTF-8"));
+        let content = match content {
+            Ok(content) => content,
+            Err(e) => {
+                quarantine(cache_path, &e);
+                return Ok(None);
+            }
+        };
+
+        if let Some(version) = newer_version(&content) {
+            static WARNED: Once = Once::new();
+            WARNED.call_once(|| {
+                eprintln!(
+                    "Warning: Color cache {} was written by a newer kitty-pane-bg (format {}, this build reads up to {}); colors will not be saved until kitty-pane-bg is upgraded or the file is removed",
+                    cache_path.display(),
+                    version,
+                    CACHE_VERSION
+                );
+            });
+            let mut cache = Self::new();
+            cache.read_only = true;
+            return Ok(Some(cache));
+        }
+
+        match Self::parse(&content) {
+            Ok(cache) => Ok(Some(cache)),
+            Err(e) => {
+                quarantine(cache_path, &e);
+                Ok(None)
+            }
+        }
+    }
+
+    fn parse(content: &str) -> Result<Self> {
+        let mut value: serde_json::Value =
+            serde_json::from_str(content).context("Failed to parse color cache")?;
+
+        let version = match value.get("version") {
+            None => 1,
+            Some(version) => version
+                .as_u64()
+                .and_then(|version| u32::try_from(version).ok())
+                .context("Invalid color cache version")?,
+        };
+        if version > CACHE_VERSION {
+            anyhow::bail!(
+                "color cache version {} is newer than the supported version {}",
+                version,
+                CACHE_VERSION
+            );
+        }
+
+        for from_version in version..CACHE_VERSION {
+            migrate(&mut value, from_version)?;
+        }
+        value["version"] = CACHE_VERSION.into();
+
+        serde_json::from_value(value).context("Failed to parse color cache")
+    }
+
+    /// Saves the cache without losing colors other processes saved since
+    /// this one was loaded. Tmux hooks run many renders at once, so the
+    /// file is locked for the read-merge-write and replaced atomically.
     pub fn save(&self) -> Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+
         let cache_path = Self::get_cache_path();
-        let content = serde_json::to_string_pretty(self)
+        let _lock = lock_cache_file(&cache_path)?;
+
+        let merged = match Self::read_from(&cache_path)? {
+            Some(on_disk) if on_disk.read_only => return Ok(()),
+            Some(on_disk) => Some(self.merged_with(on_disk)),
+            None => None,
+        };
+        let content = serde_json::to_string_pretty(merged.as_ref().unwrap_or(self))
             .context("Failed to serialize color cache")?;
         
-        fs::write(&cache_path, content)
+        // Write next to the cache and rename over it so readers never see a
+        // partially written file
+        let temp_path = cache_path.with_extension(format!("json.tmp.{}", std::process::id()));
+        fs::write(&temp_path, content)
             .context("Failed to write color cache file")?;
+        fs::rename(&temp_path, &cache_path)
+            .context("Failed to replace color cache file")?;
         
         Ok(())
     }
 
-    pub fn get_or_create_color(&mut self, color_key: &str) -> Rgb<u8> {
-        if let Some(cached_color) = self.colors.get(color_key) {
-            return Rgb(cached_color.rgb);
+    // Remembers the cache as read, to merge this process's changes into
+    // whatever other processes saved in the meantime
+    fn mark_loaded(&mut self) {
+        self.loaded_colors = self.colors.clone();
+        self.loaded_session_hues = self.session_hues.clone();
+        self.loaded_generation = Some(self.generation());
+    }
+
+    fn generation(&self) -> Generation {
+        Generation {
+            startup_seed: self.startup_seed,
+            tone: self.tone,
+            palette: self.palette.clone(),
+            color_vision: self.color_vision,
+            key_strategy: self.key_strategy,
+            harmony: self.harmony,
+            split_tree: self.split_tree,
+            session_families: self.session_families,
         }
+    }
 
-        // Generate new color with maximum distinctness
-        let (color, hue) = self.generate_distinct_color(color_key);
-        let cached_color = CachedColor {
-            rgb: color.0,
-            hue,
-            created_at: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs(),
+    // Applies this process's changes (relative to what it loaded) on top of
+    // the current file, colors and session hues alike. Each setting this
+    // process changed wins over the file's. Unless the merged settings are
+    // this process's, its generated colors do not fit them and only its pins
+    // are kept; unless they are the file's, the same goes for the file's.
+    fn merged_with(&self, mut on_disk: Self) -> Self {
+        let ours = self.generation();
+        let theirs = on_disk.generation();
+        let base = self.loaded_generation.clone().unwrap_or_else(|| theirs.clone());
+        let merged = Generation {
+            startup_seed: changed(base.startup_seed, ours.startup_seed, theirs.startup_seed),
+            tone: changed(base.tone, ours.tone, theirs.tone),
+            palette: changed(base.palette, ours.palette.clone(), theirs.palette.clone()),
+            color_vision: changed(base.color_vision, ours.color_vision, theirs.color_vision),
+            key_strategy: changed(base.key_strategy, ours.key_strategy, theirs.key_strategy),
+            harmony: changed(base.harmony, ours.harmony, theirs.harmony),
+            split_tree: changed(base.split_tree, ours.split_tree, theirs.split_tree),
+            session_families: changed(
+                base.session_families,
+                ours.session_families,
+                theirs.session_families,
+            ),
         };
-        
-        self.colors.insert(color_key.to_string(), cached_color);
-        self.used_hues.push(hue);
-        color
+        if merged != theirs {
+            on_disk.clear_unpinned();
+            on_disk.clear_session_hues();
+            on_disk.balanced_keys.clear();
+        }
+        let keep_ours = merged == ours;
+        let same_settings = ours == theirs;
+
+        on_disk.startup_seed = merged.startup_seed;
+        on_disk.tone = merged.tone;
+        on_disk.palette = merged.palette;
+        on_disk.color_vision = merged.color_vision;
+        on_disk.key_strategy = merged.key_strategy;
+        on_disk.harmony = merged.harmony;
+        on_disk.split_tree = merged.split_tree;
+        on_disk.session_families = merged.session_families;
+
+        for (key, loaded) in &self.loaded_colors {
+            if !self.colors.contains_key(key) && on_disk.colors.get(key) == Some(loaded) {
+                on_disk.colors.remove(key);
+            }
+        }
+        for (key, color) in &self.colors {
+            if !keep_ours && !color.pinned {
+                continue;
+            }
+            // Unchanged colors are already in the file, unless it was
+            // generated under other settings
+            let loaded = self.loaded_colors.get(key);
+            if same_settings && loaded == Some(color) {
+                continue;
+            }
+            // Renders only mark panes as seen; that must not undo a color or
+            // pin another process set in the meantime
+            match on_disk.colors.get_mut(key) {
+                Some(current) if loaded.is_some_and(|loaded| loaded.same_color(color)) => {
+                    current.last_seen = current.last_seen.max(color.last_seen);
+                }
+                _ => {
+                    on_disk.colors.insert(key.clone(), color.clone());
+                }
+            }
+        }
+        if keep_ours {
+            on_disk.balanced_keys = self.balanced_keys.clone();
+        }
+
+        for (session, loaded) in &self.loaded_session_hues {
+            if !self.session_hues.contains_key(session)
+                && on_disk.session_hues.get(session) == Some(loaded)
+            {
+                on_disk.session_hues.remove(session);
+            }
+        }
+        for (session, &hue) in &self.session_hues {
+            let pinned = self.pinned_sessions.contains(session);
+            if !keep_ours && !pinned {
+                continue;
+            }
+            let loaded = self.loaded_session_hues.get(session);
+            if same_settings && loaded == Some(&hue) {
+                continue;
+            }
+            on_disk.session_hues.insert(session.clone(), hue);
+        }
+        on_disk
+            .pinned_sessions
+            .extend(self.pinned_sessions.iter().cloned());
+        on_disk.prune_session_hues();
+
+        on_disk
     }
 
-    pub fn remove_pane(&mut self, pane_id: &str) -> bool {
-        if let Some(cached_color) = self.colors.remove(pane_id) {
-            // Remove the hue from used_hues
-            self.used_hues.retain(|&h| (h - cached_color.hue).abs() > 1.0);
-            true
-        } else {
-            false
+    /// Makes newly generated colors readable behind the given text colors
+    pub fn set_text_contrast(&mut self, text_contrast: Option<TextContrast>) {
+        self.text_contrast = text_contrast;
+    }
+
+    /// Shifts colors as they are drawn, e.g. by the time of day
+    pub fn set_color_shift(&mut self, color_shift: ColorShift) {
+        self.color_shift = color_shift;
+    }
+
+    /// Picks pane colors from a palette instead of generated hues. Switching
+    /// to a different palette drops every cached color so panes are
+    /// reassigned from the new palette rather than mixing the two.
+    pub fn set_palette(&mut self, palette: Option<Palette>) {
+        let name = palette.as_ref().map(|p| p.name.clone());
+        if self.palette != name {
+            self.clear_unpinned();
+            self.palette = name;
         }
+        self.active_palette = palette;
     }
 
-    pub fn clean_missing_panes(&mut self, existing_color_keys: &[String]) {
-        let existing_set: std::collections::HashSet<_> = existing_color_keys.iter().collect();
-        let removed_colors: Vec<_> = self.colors.iter()
-            .filter(|(color_key, _)| !existing_set.contains(color_key))
-            .map(|(_, color)| color.hue)
+    /// Measures distinctness as seen with the given color vision deficiency.
+    /// Changing the mode drops cached colors, which were spaced for other eyes.
+    pub fn set_color_vision(&mut self, color_vision: Option<ColorVision>) {
+        if self.color_vision != color_vision {
+            self.clear_unpinned();
+            self.color_vision = color_vision;
+        }
+    }
+
+    /// Colors each window as one family following its split tree, with
+    /// split-off panes taking their parent's hue at stepped lightnesses.
+    /// Changing it drops unpinned colors. Palettes and harmonies take
+    /// precedence.
+    pub fn set_split_tree(&mut self, split_tree: bool) {
+        if self.split_tree != split_tree {
+            self.clear_unpinned();
+            self.split_tree = split_tree;
+        }
+    }
+
+    /// Gives every session a hue of its own, with windows varying in
+    /// saturation and panes in lightness around it. Changing it drops
+    /// unpinned colors. Palettes and harmonies take precedence.
+    pub fn set_session_families(&mut self, session_families: bool) {
+        if self.session_families != session_families {
+            self.clear_unpinned();
+            self.clear_session_hues();
+            self.session_families = session_families;
+        }
+    }
+
+    /// Draws each session's colors from a harmony around a base hue instead
+    /// of spreading them over the whole wheel. Changing it drops unpinned
+    /// colors. Palettes take precedence over harmonies.
+    pub fn set_harmony(&mut self, harmony: Option<Harmony>) {
+        if self.harmony != harmony {
+            self.clear_unpinned();
+            self.harmony = harmony;
+        }
+    }
+
+    /// Derives colors from a fixed seed instead of the random one the cache
+    /// was created with. Panes are then colored in key order and clashes
+    /// settled by key rather than age, so every machine and build gives the
+    /// same panes the same colors. A new seed drops unpinned colors so they
+    /// are derived again from it.
+    pub fn set_seed(&mut self, seed: u64) {
+        if self.startup_seed != seed {
+            self.clear_unpinned();
+            self.startup_seed = seed;
+        }
+        self.seeded = true;
+    }
+
+    /// Switches how panes are keyed. Colors of the given panes are moved from
+    /// their old keys to their new ones so they keep their colors.
+    pub fn set_key_strategy(&mut self, key_strategy: KeyStrategy, panes: &[TmuxPane]) {
+        if self.key_strategy == key_strategy {
+            return;
+        }
+
+        for pane in panes {
+            let old_key = self.key_strategy.key(pane);
+            let new_key = key_strategy.key(pane);
+            if old_key == new_key || self.colors.contains_key(&new_key) {
+                continue;
+            }
+            if let Some(cached_color) = self.colors.remove(&old_key) {
+                self.colors.insert(new_key, cached_color);
+            }
+        }
+
+        self.key_strategy = key_strategy;
+    }
+
+    pub fn key_for(&self, pane: &TmuxPane) -> String {
+        self.key_strategy.key(pane)
+    }
+
+    /// Switches to a new tone, regenerating cached colors from their hues
+    /// so every pane keeps its identity across theme changes
+    pub fn set_tone(&mut self, tone: PaneTone) {
+        if self.tone == tone {
+            return;
+        }
+
+        self.tone = tone;
+        // Palette colors are fixed and do not follow the tone
+        if self.palette.is_some() {
+            return;
+        }
+        // Harmony and family colors vary in lightness as well, so derive
+        // them afresh
+        if self.harmony.is_some() || self.split_tree || self.session_families {
+            self.clear_unpinned();
+            return;
+        }
+
+        let recolored: Vec<(String, Rgb<u8>)> = self
+            .colors
+            .iter()
+            .filter(|(_, cached)| !cached.pinned)
+            .map(|(key, cached)| (key.clone(), self.color_for_hue(cached.hue)))
             .collect();
-        
-        self.colors.retain(|color_key, _| existing_set.contains(color_key));
-        
-        // Clean up used_hues
-        for hue in removed_colors {
-            self.used_hues.retain(|&h| (h - hue).abs() > 1.0);
+        for (key, color) in recolored {
+            if let Some(cached) = self.colors.get_mut(&key) {
+                cached.rgb = color.0;
+            }
         }
     }
 
-    fn generate_distinct_color(&self, pane_id: &str) -> (Rgb<u8>, f32) {
-        // Use startup seed and pane ID for base randomness
-        let mut hasher = std::collections::hash_map::DefaultHasher::new();
-        use std::hash::{Hash, Hasher};
-        
-        self.startup_seed.hash(&mut hasher);
-        pane_id.hash(&mut hasher);
-        
-        let hash_value = hasher.finish();
-        
-        // Find the most distinct hue
-        let candidate_hue = (hash_value % 360) as f32;
-        let hue = self.find_most_distinct_hue(candidate_hue);
-        
-        // Bright pastel colors: high lightness (75-85%), medium-high saturation (70-80%)
-        let saturation = 0.70 + ((hash_value >> 8) % 11) as f32 / 100.0; // 70-80%
-        let lightness = 0.75 + ((hash_value >> 16) % 11) as f32 / 100.0; // 75-85%
-        
-        (hsv_to_rgb(hue, saturation, lightness), hue)
+    pub fn get_or_create_pane_color(&mut self, pane: &TmuxPane) -> Rgb<u8> {
+        let color_key = self.key_for(pane);
+        if let Some(cached_color) = self.colors.get(&color_key) {
+            return Rgb(cached_color.rgb);
+        }
+
+        self.insert_generated(&color_key, Some(&pane.session_id), 0, &[])
     }
 
-    fn find_most_distinct_hue(&self, preferred_hue: f32) -> f32 {
-        if self.used_hues.is_empty() {
-            return preferred_hue;
+    /// The pane's color as it should be drawn right now: its cached color
+    /// with the color shift applied, kept readable behind the text colors
+    pub fn display_color(&mut self, pane: &TmuxPane) -> Rgb<u8> {
+        let color = self.get_or_create_pane_color(pane);
+        if self.color_shift.is_identity() {
+            return color;
         }
 
-        // Try the preferred hue first
-        if self.is_hue_distinct(preferred_hue) {
-            return preferred_hue;
+        let shifted = self.color_shift.apply(Oklch::from_rgb(color));
+        match &self.text_contrast {
+            Some(text_contrast) => text_contrast.ensure(shifted),
+            None => shifted.to_rgb(),
         }
+    }
 
-        // Find the hue with maximum minimum distance to all used hues
-        let mut best_hue = preferred_hue;
-        let mut best_min_distance = 0.0f32;
+    /// Colors every pane, by session family or split tree when enabled and
+    /// otherwise by adjacency
+    pub fn assign_pane_colors(&mut self, panes: &[TmuxPane]) {
+        let mut sorted;
+        let panes = if self.seeded {
+            sorted = panes.to_vec();
+            sorted.sort_by_key(|pane| self.key_for(pane));
+            &sorted
+        } else {
+            panes
+        };
 
-        // Check hues in steps around the color wheel
-        for step in 0..36 {
-            let test_hue = (preferred_hue + step as f32 * 10.0) % 360.0;
-            let min_distance = self.used_hues.iter()
-                .map(|&used_hue| hue_distance(test_hue, used_hue))
-                .fold(360.0f32, f32::min);
+        let generated = self.palette.is_none() && self.harmony.is_none();
+        if generated && self.session_families {
+            self.assign_by_session(panes);
+        } else if generated && self.split_tree {
+            self.assign_by_split_tree(panes);
+        } else {
+            self.assign_by_adjacency(panes);
+        }
+    }
 
-            if min_distance > best_min_distance {
-                best_min_distance = min_distance;
-                best_hue = test_hue;
+    // Whether colors come from session or split-tree families rather than
+    // one by one
+    fn uses_families(&self) -> bool {
+        self.palette.is_none()
+            && self.harmony.is_none()
+            && (self.session_families || self.split_tree)
+    }
+
+    // Every session has a hue, each of its windows a saturation picked by
+    // window index, and the panes of a window step through lightnesses in
+    // pane order, so the background alone tells which session is showing
+    fn assign_by_session(&mut self, panes: &[TmuxPane]) {
+        let mut windows: Vec<(&str, u32)> = Vec::new();
+        for pane in panes {
+            let window = (pane.session_id.as_str(), pane.window_index);
+            if !windows.contains(&window) {
+                windows.push(window);
             }
         }
 
-        best_hue
+        for (session, window_index) in windows {
+            let mut window_panes: Vec<&TmuxPane> = panes
+                .iter()
+                .filter(|pane| pane.session_id == session && pane.window_index == window_index)
+                .collect();
+            window_panes.sort_by_key(|pane| pane.pane_index);
+            let hue = self.session_hue(window_panes[0]);
+
+            // Colors derived from an earlier hue of the session, such as
+            // before one was imported, follow the current one
+            for pane in &window_panes {
+                let key = self.key_for(pane);
+                let stale = self.colors.get(&key).is_some_and(|cached_color| {
+                    !cached_color.pinned && cached_color.family.is_some_and(|slot| slot.hue != hue)
+                });
+                if stale {
+                    self.colors.remove(&key);
+                }
+            }
+
+            let family: Vec<FamilyMember> = window_panes
+                .into_iter()
+                .map(|pane| FamilyMember {
+                    pane,
+                    hue_offset: 0.0,
+                    split: None,
+                })
+                .collect();
+
+            let saturation =
+                WINDOW_SATURATION_STEPS[window_index as usize % WINDOW_SATURATION_STEPS.len()];
+            self.color_family(&family, hue, self.tone.chroma * saturation);
+        }
     }
 
-    fn is_hue_distinct(&self, hue: f32) -> bool {
-        const MIN_HUE_DISTANCE: f32 = 30.0; // Minimum 30 degrees apart
-        
-        self.used_hues.iter().all(|&used_hue| {
-            hue_distance(hue, used_hue) >= MIN_HUE_DISTANCE
-        })
+    // The hue of the pane's session: one imported for its name, else the
+    // one kept for its id, else one kept for its name by caches from before
+    // sessions were told apart by id. The first time a session is seen it
+    // picks a hue that stands out from the other sessions' hues.
+    fn session_hue(&mut self, pane: &TmuxPane) -> f32 {
+        let by_name = self.session_hues.get(&pane.session_name);
+        let by_id = self.session_hues.get(&pane.session_id);
+        let known = if self.pinned_sessions.contains(&pane.session_name) {
+            by_name.or(by_id)
+        } else {
+            by_id.or(by_name)
+        };
+        let hue = match known {
+            Some(&hue) => hue,
+            None => {
+                let used_colors: Vec<(Rgb<u8>, f32)> = self
+                    .session_hues
+                    .values()
+                    .map(|&hue| (self.tone.color(hue).to_rgb(), SESSION_WEIGHT))
+                    .collect();
+                let base_hue = self.session_base_hue(&pane.session_id);
+                self.find_most_distinct_hue(base_hue, &used_colors)
+            }
+        };
+        self.session_hues.insert(pane.session_id.clone(), hue);
+        hue
     }
 
-    #[allow(dead_code)]
-    pub fn get_base_colors() -> Vec<Rgb<u8>> {
-        vec![
-            Rgb([255, 99, 71]),   // Tomato
-            Rgb([60, 179, 113]),  // Medium Sea Green
-            Rgb([100, 149, 237]), // Cornflower Blue
-            Rgb([255, 165, 0]),   // Orange
-            Rgb([147, 112, 219]), // Medium Purple
-            Rgb([255, 20, 147]),  // Deep Pink
-            Rgb([0, 206, 209]),   // Dark Turquoise
-            Rgb([255, 215, 0]),   // Gold
-        ]
+    // Colors follow how each window was split: the first pane's hue is the
+    // window's family and every pane takes its own lightness step of it,
+    // while each nested split turns the hue a little further so the nesting
+    // shows. A freshly split pane takes its color from the pane it came
+    // from. Windows whose layout cannot be read fall back to adjacency.
+    fn assign_by_split_tree(&mut self, panes: &[TmuxPane]) {
+        let mut window_ids: Vec<&str> = Vec::new();
+        for pane in panes {
+            if !window_ids.contains(&pane.window_id.as_str()) {
+                window_ids.push(&pane.window_id);
+            }
+        }
+
+        for window_id in window_ids {
+            let window_panes: Vec<TmuxPane> = panes
+                .iter()
+                .filter(|pane| pane.window_id == window_id)
+                .cloned()
+                .collect();
+
+            let root = match parse_window_layout(&window_panes[0].window_layout) {
+                Ok(root) => root,
+                Err(e) => {
+                    eprintln!("Warning: Could not read layout of window {}: {}", window_id, e);
+                    self.assign_by_adjacency(&window_panes);
+                    continue;
+                }
+            };
+
+            // In tree order, so the family starts from the root pane
+            let mut positions = Vec::new();
+            split_positions(&root, 0.0, 0, &mut 0, &mut positions);
+            let family: Vec<FamilyMember> = positions
+                .into_iter()
+                .filter_map(|(id, hue_offset, split)| {
+                    let pane = window_panes.iter().find(|pane| pane.id == id)?;
+                    Some(FamilyMember {
+                        pane,
+                        hue_offset,
+                        split: Some(split),
+                    })
+                })
+                .collect();
+            self.color_split_family(&family);
+        }
     }
 
-    #[allow(dead_code)]
-    pub fn list_cached_panes(&self) -> Vec<String> {
-        self.colors.keys().cloned().collect()
+    // Colors the family from the base hue of its colored panes, or from a
+    // new distinct hue for the first pane
+    fn color_split_family(&mut self, members: &[FamilyMember]) {
+        let Some(first) = members.first().map(|member| member.pane) else {
+            return;
+        };
+
+        let cached: Vec<&CachedColor> = members
+            .iter()
+            .filter_map(|member| self.colors.get(&self.key_for(member.pane)))
+            .collect();
+        let hue = match cached.iter().find_map(|cached_color| cached_color.family) {
+            Some(slot) => slot.hue,
+            None => match cached.first() {
+                Some(cached_color) => cached_color.hue,
+                None => {
+                    let first_key = self.key_for(first);
+                    self.generate_distinct_color(&first_key, Some(&first.session_id), 0, &[])
+                        .1
+                }
+            },
+        };
+
+        self.color_family(members, hue, self.tone.chroma);
     }
-}
 
-// Calculate the minimum distance between two hues on the color wheel
-fn hue_distance(hue1: f32, hue2: f32) -> f32 {
-    let diff = (hue1 - hue2).abs();
-    diff.min(360.0 - diff)
-}
-
-fn hsv_to_rgb(h: f32, s: f32, v: f32) -> Rgb<u8> {
-    let c = v * s;
-    let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
-    let m = v - c;
-    
-    let (r, g, b) = if h < 60.0 {
-        (c, x, 0.0)
-    } else if h < 120.0 {
-        (x, c, 0.0)
-    } else if h < 180.0 {
-        (0.0, c, x)
-    } else if h < 240.0 {
-        (0.0, x, c)
-    } else if h < 300.0 {
-        (x, 0.0, c)
-    } else {
-        (c, 0.0, x)
-    };
-    
-    Rgb([
-        ((r + m) * 255.0) as u8,
-        ((g + m) * 255.0) as u8,
-        ((b + m) * 255.0) as u8,
-    ])
+    // Colors uncolored panes from the family's hue, turned by each pane's
+    // offset, at the first step no sibling has taken. A pane in a split
+    // instead starts from its closest colored relative: it takes that
+    // pane's hue and the free step closest to it in lightness. A step whose
+    // color text contrast pushed onto a sibling's is skipped.
+    fn color_family(&mut self, members: &[FamilyMember], hue: f32, chroma: f32) {
+        let keys: Vec<String> = members
+            .iter()
+            .map(|member| self.key_for(member.pane))
+            .collect();
+        let siblings: Vec<&CachedColor> =
+            keys.iter().filter_map(|key| self.colors.get(key)).collect();
+        let mut used_steps: Vec<u32> = siblings
+            .iter()
+            .filter_map(|cached_color| cached_color.family)
+            .map(|slot| slot.step)
+            .collect();
+        let mut sibling_colors: Vec<(Rgb<u8>, f32)> = siblings
+            .iter()
+            .map(|cached_color| (Rgb(cached_color.rgb), 1.0))
+            .collect();
+        let lightnesses = self.family_lightnesses();
+
+        let turn = |step: u32| (step as usize / lightnesses.len()) as f32 * FAMILY_WRAP_HUE;
+
+        for (i, (member, key)) in members.iter().zip(&keys).enumerate() {
+            if self.colors.contains_key(key) {
+                continue;
+            }
+
+            // The pane's hue before any turn, and the step it stays close to
+            let (pane_hue, near_step) = match self.split_relative(members, &keys, i) {
+                Some(relative) => {
+                    let relative_color = &self.colors[&keys[relative]];
+                    let step = relative_color.family.map_or(0, |slot| slot.step);
+                    let hue = relative_color.hue - turn(step) + member.hue_offset
+                        - members[relative].hue_offset;
+                    (hue, Some(step))
+                }
+                None => (hue + member.hue_offset, None),
+            };
+
+            let rerolled_step = self
+                .rerolled_step
+                .as_ref()
+                .filter(|(rerolled_key, _)| rerolled_key == key)
+                .map(|&(_, step)| step);
+            let mut steps: Vec<u32> = (0..=FAMILY_MAX_STEPS + used_steps.len() as u32)
+                .filter(|step| !used_steps.contains(step) && rerolled_step != Some(*step))
+                .collect();
+            if let Some(near) = near_step {
+                let wrap = |step: u32| step as usize / lightnesses.len();
+                let lightness = |step: u32| lightnesses[step as usize % lightnesses.len()];
+                steps.sort_by_key(|&step| {
+                    let levels = (lightness(step) - lightness(near)) / FAMILY_LIGHTNESS_STEP;
+                    (wrap(step).abs_diff(wrap(near)), levels.abs().round() as u32)
+                });
+            }
+
+            let color_at = |step: u32| {
+                let slot = lightnesses[step as usize % lightnesses.len()];
+                let oklch = Oklch::new(slot, chroma, pane_hue + turn(step));
+                let color = match &self.text_contrast {
+                    Some(text_contrast) => text_contrast.ensure(oklch),
+                    None => oklch.to_rgb(),
+                };
+                (color, oklch.h)
+            };
+            let (step, (color, color_hue)) = steps
+                .iter()
+                .take(FAMILY_MAX_STEPS as usize)
+                .map(|&step| (step, color_at(step)))
+                .find(|(_, (color, _))| {
+                    self.min_delta_e(*color, &sibling_colors) >= FAMILY_MIN_DELTA_E
+                })
+                .unwrap_or_else(|| (steps[0], color_at(steps[0])));
+            used_steps.push(step);
+            sibling_colors.push((color, 1.0));
+
+            let mut cached_color = CachedColor::new(color, color_hue, false);
+            cached_color.session = Some(member.pane.session_id.clone());
+            cached_color.family = Some(FamilySlot { hue, step });
+            self.colors.insert(key.clone(), cached_color);
+        }
+    }
+
+    // The colored member closest to member `i` in tree order, preferring
+    // members of its own split and, at equal distance, the one before it.
+    // None outside split trees.
+    fn split_relative(&self, members: &[FamilyMember], keys: &[String], i: usize) -> Option<usize> {
+        let split = members[i].split?;
+        (0..members.len())
+            .filter(|&j| j != i && self.colors.contains_key(&keys[j]))
+            .min_by_key(|&j| (members[j].split != Some(split), i.abs_diff(j), j > i))
+    }
+
+    // Lightnesses a family steps through: the tone's, then alternately
+    // lighter and darker, as far as they stay within range and gamut
+    fn family_lightnesses(&self) -> Vec<f32> {
+        let steps = (FAMILY_LIGHTNESS_RANGE / FAMILY_LIGHTNESS_STEP).round() as i32;
+        let lightnesses: Vec<f32> = (0..=2 * steps)
+            .map(|n| if n % 2 == 1 { (n + 1) / 2 } else { -(n / 2) })
+            .map(|n| self.tone.lightness + n as f32 * FAMILY_LIGHTNESS_STEP)
+            .filter(|lightness| (0.2..=0.95).contains(lightness))
+            .collect();
+        if lightnesses.is_empty() {
+            return vec![self.tone.lightness];
+        }
+        lightnesses
+    }
+
+    // Treats the layout as a graph coloring problem: new panes are colored
+    // most-connected first, each with the color that stands out most against
+    // the panes it shares an edge with. Cached colors are kept unless two
+    // touching panes are too alike, in which case the newer unpinned one is
+    // recolored. A recolored pane that gets its old color back keeps its
+    // cached entry, since palettes and harmonies may have nothing better.
+    fn assign_by_adjacency(&mut self, panes: &[TmuxPane]) {
+        let keys: Vec<String> = panes.iter().map(|pane| self.key_for(pane)).collect();
+        let neighbors = pane_neighbors(panes);
+        let neighbor_keys = |i: usize| -> Vec<String> {
+            neighbors[i].iter().map(|&j| keys[j].clone()).collect()
+        };
+        let mut clashing: HashMap<String, CachedColor> = HashMap::new();
+
+        for (i, key) in keys.iter().enumerate() {
+            let Some(cached_color) = self.colors.get(key) else {
+                continue;
+            };
+            if cached_color.pinned {
+                continue;
+            }
+
+            let clashes = neighbors[i].iter().any(|&j| {
+                self.colors.get(&keys[j]).is_some_and(|neighbor| {
+                    // With a fixed seed the later key gives way, as the
+                    // creation times differ from machine to machine
+                    let older = match self.seeded {
+                        true => keys[j] < *key,
+                        false => neighbor.created_at <= cached_color.created_at,
+                    };
+                    (neighbor.pinned || older)
+                        && keys[j] != *key
+                        && self.min_delta_e(Rgb(cached_color.rgb), &[(Rgb(neighbor.rgb), 1.0)])
+                            < MIN_DELTA_E
+                })
+            });
+            if clashes {
+                if let Some(cached_color) = self.colors.remove(key) {
+                    clashing.insert(key.clone(), cached_color);
+                }
+            }
+        }
+
+        let mut uncolored: Vec<usize> = (0..panes.len())
+            .filter(|&i| !self.colors.contains_key(&keys[i]))
+            .collect();
+        uncolored.sort_by(|&a, &b| {
+            neighbors[b]
+                .len()
+                .cmp(&neighbors[a].len())
+                .then_with(|| keys[a].cmp(&keys[b]))
+        });
+        for i in uncolored {
+            if self.colors.contains_key(&keys[i]) {
+                continue;
+            }
+            let color =
+                self.insert_generated(&keys[i], Some(&panes[i].session_id), 0, &neighbor_keys(i));
+            if let Some(old) = clashing.remove(&keys[i]) {
+                if old.rgb == color.0 {
+                    self.colors.insert(keys[i].clone(), old);
+                }
+            }
+        }
+    }
+
+    // Generates a new color distinct from every cached one, and most of all
+    // from the given neighbors, and caches it
+    fn insert_generated(
+        &mut self,
+        color_key: &str,
+        session: Option<&str>,
+        salt: u64,
+        neighbors: &[String],
+    ) -> Rgb<u8> {
+        let (color, hue) = self.generate_distinct_color(color_key, session, salt, neighbors);
+        let mut cached_color = CachedColor::new(color, hue, false);
+        cached_color.session = session.map(str::to_string);
+        self.colors.insert(color_key.to_string(), cached_color);
+        color
+    }
+
+    pub fn remove_pane(&mut self, pane_id: &str) -> bool {
+        self.colors.remove(pane_id).is_some()
+    }
+
+    /// Pins a specific color to a pane
+    pub fn pin_color(&mut self, color_key: &str, color: Rgb<u8>) {
+        let hue = Oklch::from_rgb(color).h;
+        self.colors
+            .insert(color_key.to_string(), CachedColor::new(color, hue, true));
+    }
+
+    /// Locks or unlocks an existing color. Returns false if the pane has no color.
+    pub fn set_pinned(&mut self, color_key: &str, pinned: bool) -> bool {
+        match self.colors.get_mut(color_key) {
+            Some(cached_color) => {
+                cached_color.pinned = pinned;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Gives an unpinned pane a new color, distinct from its old one and
+    /// from every other pane. A family color moves to another step of its
+    /// family among the given panes. Returns false if the pane is unknown
+    /// or pinned.
+    pub fn reroll(&mut self, color_key: &str, panes: &[TmuxPane]) -> bool {
+        let Some(cached_color) = self.colors.get(color_key) else {
+            return false;
+        };
+        if cached_color.pinned {
+            return false;
+        }
+
+        if self.uses_families() {
+            // A pane that is gone gets a new step once it is back
+            let old_step = cached_color.family.map(|slot| slot.step);
+            self.colors.remove(color_key);
+            if let Some(pane) = panes.iter().find(|pane| self.key_for(pane) == color_key) {
+                let window_panes: Vec<TmuxPane> = panes
+                    .iter()
+                    .filter(|other| other.window_id == pane.window_id)
+                    .cloned()
+                    .collect();
+                self.rerolled_step = old_step.map(|step| (color_key.to_string(), step));
+                self.assign_pane_colors(&window_panes);
+                self.rerolled_step = None;
+            }
+            return true;
+        }
+
+        // Generate while the old color is still in use so it is avoided
+        let session = cached_color.session.clone();
+        let salt = rand::thread_rng().gen_range(1..u64::MAX);
+        self.insert_generated(color_key, session.as_deref(), salt, &[]);
+        true
+    }
+
+    /// Picks a new seed and regenerates every unpinned color from it.
+    /// Family colors are dropped instead, along with the session hues, and
+    /// derived from the new seed by the next render. Returns the number of
+    /// rerolled colors.
+    pub fn reroll_all(&mut self) -> usize {
+        self.startup_seed = rand::thread_rng().gen();
+
+        let mut keys: Vec<(String, Option<String>)> = self
+            .colors
+            .iter()
+            .filter(|(_, cached)| !cached.pinned)
+            .map(|(key, cached)| (key.clone(), cached.session.clone()))
+            .collect();
+        keys.sort();
+
+        self.clear_unpinned();
+        if self.uses_families() {
+            self.clear_session_hues();
+            return keys.len();
+        }
+        for (key, session) in &keys {
+            self.insert_generated(key, session.as_deref(), 0, &[]);
+        }
+        keys.len()
+    }
+
+    /// Re-spreads the hues of the visible panes for even spacing after panes
+    /// came or went, moving each by `1 - stability` of the way. Pinned colors
+    /// stay put and push the others away. Returns the number of moved colors.
+    pub fn rebalance(&mut self, visible_keys: &[String], rebalance: &Rebalance) -> usize {
+        // Palette and harmony colors come from fixed sets, families from the layout
+        if self.palette.is_some()
+            || self.harmony.is_some()
+            || self.split_tree
+            || self.session_families
+        {
+            return 0;
+        }
+
+        let mut keys: Vec<String> = visible_keys
+            .iter()
+            .filter(|key| self.colors.contains_key(*key))
+            .cloned()
+            .collect();
+        keys.sort();
+        keys.dedup();
+        if keys == self.balanced_keys {
+            return 0;
+        }
+
+        let hues: Vec<(f32, bool)> = keys
+            .iter()
+            .map(|key| (self.colors[key].hue, self.colors[key].pinned))
+            .collect();
+        let targets = spread_hues(&hues);
+        let movement = 1.0 - rebalance.stability.clamp(0.0, 1.0);
+
+        let mut moved = 0;
+        for ((key, &(hue, pinned)), target) in keys.iter().zip(&hues).zip(targets) {
+            if pinned {
+                continue;
+            }
+            let step = hue_difference(hue, target) * movement;
+            if step.abs() < 1.0 {
+                continue;
+            }
+
+            let new_hue = (hue + step).rem_euclid(360.0);
+            let color = self.color_for_hue(new_hue);
+            if let Some(cached_color) = self.colors.get_mut(key) {
+                cached_color.hue = new_hue;
+                cached_color.rgb = color.0;
+                moved += 1;
+            }
+        }
+
+        self.balanced_keys = keys;
+        moved
+    }
+
+    fn clear_unpinned(&mut self) {
+        self.colors.retain(|_, cached| cached.pinned);
+    }
+
+    fn clear_session_hues(&mut self) {
+        let pinned = &self.pinned_sessions;
+        self.session_hues.retain(|session, _| pinned.contains(session));
+    }
+
+    /// Fixes the hue of the session with this name, as when importing it.
+    /// The next render moves the session's unpinned family colors to it.
+    pub fn set_session_hue(&mut self, session: &str, hue: f32) {
+        self.session_hues
+            .insert(session.to_string(), hue.rem_euclid(360.0));
+        self.pinned_sessions.insert(session.to_string());
+    }
+
+    pub fn set_retention(&mut self, retention: RetentionPolicy) {
+        self.retention = retention;
+    }
+
+    /// Marks the given panes as seen and evicts colors of other panes per
+    /// the retention policy; new colors are then only kept apart from the
+    /// given panes' colors. Returns the number of evicted colors.
+    pub fn clean_missing_panes(&mut self, existing_color_keys: &[String]) -> usize {
+        let now = unix_now();
+        for key in existing_color_keys {
+            if let Some(cached_color) = self.colors.get_mut(key) {
+                cached_color.last_seen = now;
+            }
+        }
+
+        let existing_set: HashSet<String> = existing_color_keys.iter().cloned().collect();
+        let evicted = self.evict(now, |key| existing_set.contains(key));
+        self.live_keys = Some(existing_set);
+        evicted
+    }
+
+    /// Applies the retention policy without marking any pane as seen,
+    /// sparing the colors of the given live panes
+    pub fn gc(&mut self, live_color_keys: &[String]) -> usize {
+        let live_set: HashSet<&String> = live_color_keys.iter().collect();
+        self.evict(unix_now(), |key| live_set.contains(key))
+    }
+
+    fn evict(&mut self, now: u64, is_visible: impl Fn(&String) -> bool) -> usize {
+        let before = self.colors.len();
+        let grace_period = self.retention.grace_period_secs;
+
+        // Vanished panes past their grace period, pinned or not, since pane
+        // ids get reused and a stale pin would land on an unrelated pane
+        self.colors.retain(|key, cached_color| {
+            is_visible(key) || now.saturating_sub(cached_color.last_seen_at()) <= grace_period
+        });
+
+        // Then trim to the size limit, never touching visible or pinned colors
+        if self.colors.len() > self.retention.max_entries {
+            let mut candidates: Vec<(u64, String)> = self
+                .colors
+                .iter()
+                .filter(|(key, cached_color)| !is_visible(key) && !cached_color.pinned)
+                .map(|(key, cached_color)| {
+                    let rank = match self.retention.evict_by {
+                        Eviction::LeastRecentlyUsed => cached_color.last_seen_at(),
+                        Eviction::Oldest => cached_color.created_at,
+                    };
+                    (rank, key.clone())
+                })
+                .collect();
+            candidates.sort();
+
+            let excess = self.colors.len() - self.retention.max_entries;
+            for (_, key) in candidates.into_iter().take(excess) {
+                self.colors.remove(&key);
+            }
+        }
+
+        self.prune_session_hues();
+        before - self.colors.len()
+    }
+
+    // Session hues go with the last cached color of their session, unless
+    // pinned
+    fn prune_session_hues(&mut self) {
+        let sessions: HashSet<&str> = self
+            .colors
+            .values()
+            .filter_map(|cached_color| cached_color.session.as_deref())
+            .collect();
+        let pinned = &self.pinned_sessions;
+        self.session_hues
+            .retain(|session, _| sessions.contains(session.as_str()) || pinned.contains(session));
+    }
+
+    fn generate_distinct_color(
+        &self,
+        pane_id: &str,
+        session: Option<&str>,
+        salt: u64,
+        neighbors: &[String],
+    ) -> (Rgb<u8>, f32) {
+        let hash_value = stable_hash(self.startup_seed, pane_id, salt);
+        let used_colors = self.used_colors(neighbors);
+
+        if let Some(palette) = &self.active_palette {
+            let candidates: Vec<Rgb<u8>> = palette.rgb_colors().collect();
+            if let Some(index) = self.pick_most_distinct(&candidates, hash_value, &used_colors) {
+                let color = candidates[index];
+                let hue = Oklch::from_rgb(color).h;
+                let color = match &self.text_contrast {
+                    Some(text_contrast) => text_contrast.ensure(Oklch::from_rgb(color)),
+                    None => color,
+                };
+                return (color, hue);
+            }
+        }
+
+        if let Some(harmony) = self.harmony {
+            let base_hue = self.session_base_hue(session.unwrap_or_default());
+            let scheme = harmony.colors(base_hue, &self.tone);
+            let candidates: Vec<Rgb<u8>> = scheme
+                .iter()
+                .map(|&color| match &self.text_contrast {
+                    Some(text_contrast) => text_contrast.ensure(color),
+                    None => color.to_rgb(),
+                })
+                .collect();
+            if let Some(index) = self.pick_most_distinct(&candidates, hash_value, &used_colors) {
+                return (candidates[index], scheme[index].h);
+            }
+        }
+        
+        // Find the most distinct hue at the tone's fixed lightness and chroma
+        let candidate_hue = (hash_value % 360) as f32;
+        let hue = self.find_most_distinct_hue(candidate_hue, &used_colors);
+        
+        (self.color_for_hue(hue), hue)
+    }
+
+    fn color_for_hue(&self, hue: f32) -> Rgb<u8> {
+        match &self.text_contrast {
+            Some(text_contrast) => text_contrast.ensure(self.tone.color(hue)),
+            None => self.tone.color(hue).to_rgb(),
+        }
+    }
+
+    // Colors of live panes with the weight their difference counts with
+    fn used_colors(&self, neighbors: &[String]) -> Vec<(Rgb<u8>, f32)> {
+        self.colors
+            .iter()
+            .filter(|(key, _)| self.live_keys.as_ref().is_none_or(|live| live.contains(*key)))
+            .map(|(key, cached_color)| {
+                let weight = if neighbors.contains(key) { NEIGHBOR_WEIGHT } else { 1.0 };
+                (Rgb(cached_color.rgb), weight)
+            })
+            .collect()
+    }
+
+    fn find_most_distinct_hue(&self, preferred_hue: f32, used_colors: &[(Rgb<u8>, f32)]) -> f32 {
+        if used_colors.is_empty() {
+            return preferred_hue;
+        }
+
+        // Try the preferred hue first
+        if self.min_delta_e(self.tone.color(preferred_hue).to_rgb(), used_colors) >= MIN_DELTA_E {
+            return preferred_hue;
+        }
+
+        // Find the hue with maximum minimum CIEDE2000 distance to all used colors
+        let mut best_hue = preferred_hue;
+        let mut best_min_distance = 0.0f32;
+
+        // Check hues in steps around the color wheel
+        for step in 0..36 {
+            let test_hue = (preferred_hue + step as f32 * 10.0) % 360.0;
+            let min_distance = self.min_delta_e(self.tone.color(test_hue).to_rgb(), used_colors);
+
+            if min_distance > best_min_distance {
+                best_min_distance = min_distance;
+                best_hue = test_hue;
+            }
+        }
+
+        best_hue
+    }
+
+    // Smallest weighted perceptual difference between a color and any of the
+    // used colors, as seen through the configured color vision deficiency
+    fn min_delta_e(&self, color: Rgb<u8>, used_colors: &[(Rgb<u8>, f32)]) -> f32 {
+        let seen = |rgb: Rgb<u8>| match self.color_vision {
+            Some(color_vision) => color_vision.simulate(rgb),
+            None => rgb,
+        };
+
+        let color = seen(color);
+        used_colors
+            .iter()
+            .map(|&(used, weight)| delta_e(color, seen(used)) * weight)
+            .fold(f32::MAX, f32::min)
+    }
+
+    // Counterpart of find_most_distinct_hue for a fixed set of colors
+    // (palette or harmony): starting from the hashed entry, take the
+    // candidate furthest from every color already in use
+    fn pick_most_distinct(
+        &self,
+        candidates: &[Rgb<u8>],
+        hash_value: u64,
+        used_colors: &[(Rgb<u8>, f32)],
+    ) -> Option<usize> {
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let start = (hash_value % candidates.len() as u64) as usize;
+        if used_colors.is_empty() || self.min_delta_e(candidates[start], used_colors) >= MIN_DELTA_E {
+            return Some(start);
+        }
+
+        let mut best = start;
+        let mut best_min_distance = 0.0f32;
+        for offset in 0..candidates.len() {
+            let index = (start + offset) % candidates.len();
+            let min_distance = self.min_delta_e(candidates[index], used_colors);
+            if min_distance > best_min_distance {
+                best_min_distance = min_distance;
+                best = index;
+            }
+        }
+
+        Some(best)
+    }
+
+    // Base hue of a session's harmony, fixed by the seed and session id
+    fn session_base_hue(&self, session: &str) -> f32 {
+        (stable_hash(self.startup_seed, &format!("session:{}", session), 0) % 360) as f32
+    }
+
+    #[allow(dead_code)]
+    pub fn list_cached_panes(&self) -> Vec<String> {
+        self.colors.keys().cloned().collect()
+    }
+}
+
+/// The cache file of one tmux server
+#[derive(Debug)]
+pub struct ServerCache {
+    pub path: PathBuf,
+    pub server: Option<String>,
+    pub colors: usize,
+}
+
+impl ServerCache {
+    pub fn is_current(&self) -> bool {
+        self.server == current_server()
+    }
+
+    /// Whether the server still accepts connections. A server that crashed
+    /// can leave its socket file behind.
+    #[cfg(unix)]
+    pub fn is_live(&self) -> bool {
+        self.server.as_ref().is_some_and(|socket_path| {
+            std::os::unix::net::UnixStream::connect(socket_path).is_ok()
+        })
+    }
+
+    /// Whether the server's socket still exists, without Unix sockets to
+    /// try connecting to it
+    #[cfg(not(unix))]
+    pub fn is_live(&self) -> bool {
+        self.server
+            .as_ref()
+            .is_some_and(|socket_path| Path::new(socket_path).exists())
+    }
+
+    /// Deletes the cache along with its lock file
+    pub fn remove(&self) -> Result<()> {
+        fs::remove_file(&self.path)
+            .with_context(|| format!("Failed to remove {}", self.path.display()))?;
+        let _ = fs::remove_file(self.path.with_extension("json.lock"));
+        Ok(())
+    }
+}
+
+// File name for a server's cache: its socket path with separators replaced
+fn server_namespace(socket_path: Option<&str>) -> String {
+    match socket_path {
+        Some(socket_path) => socket_path
+            .trim_start_matches('/')
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                    c
+                } else {
+                    '-'
+                }
+            })
+            .collect(),
+        None => "no-server".to_string(),
+    }
+}
+
+// A pane of a family, with its hue offset and, in a split tree, the split
+// it sits in
+struct FamilyMember<'a> {
+    pane: &'a TmuxPane,
+    hue_offset: f32,
+    split: Option<usize>,
+}
+
+// Each pane's hue offset within its window and the split it sits in: panes
+// of one split share an offset, and every split nested in another turns it
+// one more step. Splits are numbered from 1 in tree order.
+fn split_positions(
+    cell: &LayoutCell,
+    offset: f32,
+    split: usize,
+    splits: &mut usize,
+    positions: &mut Vec<(String, f32, usize)>,
+) {
+    match cell {
+        LayoutCell::Pane(id) => positions.push((id.clone(), offset, split)),
+        LayoutCell::Split(children) => {
+            *splits += 1;
+            let split = *splits;
+            let mut nested = 0;
+            for child in children {
+                let child_offset = match child {
+                    LayoutCell::Split(_) => {
+                        nested += 1;
+                        offset + nested as f32 * SPLIT_HUE_STEP
+                    }
+                    LayoutCell::Pane(_) => offset,
+                };
+                split_positions(child, child_offset, split, splits, positions);
+            }
+        }
+    }
+}
+
+// This process's value of a setting if it changed it since loading, else
+// the file's
+fn changed<T: PartialEq>(loaded: T, ours: T, theirs: T) -> T {
+    if ours != loaded {
+        ours
+    } else {
+        theirs
+    }
+}
+
+// Signed shortest turn from one hue to another, in degrees
+fn hue_difference(from: f32, to: f32) -> f32 {
+    (to - from + 540.0).rem_euclid(360.0) - 180.0
+}
+
+// Moves every unpinned hue to the middle of its neighbours on the wheel until
+// they settle, evenly spaced between the pinned hues. Neighbours never cross,
+// so the order of hues around the wheel is kept.
+fn spread_hues(hues: &[(f32, bool)]) -> Vec<f32> {
+    let mut spread: Vec<f32> = hues.iter().map(|&(hue, _)| hue).collect();
+    let count = spread.len();
+    if count < 2 {
+        return spread;
+    }
+
+    let mut order: Vec<usize> = (0..count).collect();
+    order.sort_by(|&a, &b| spread[a].total_cmp(&spread[b]));
+
+    for _ in 0..500 {
+        for (position, &index) in order.iter().enumerate() {
+            if hues[index].1 {
+                continue;
+            }
+
+            let previous = spread[order[(position + count - 1) % count]];
+            let next = spread[order[(position + 1) % count]];
+            // Arc from the previous hue to the next one, passing this hue
+            let arc = (next - previous).rem_euclid(360.0);
+            let arc = if arc == 0.0 { 360.0 } else { arc };
+            spread[index] = (previous + arc / 2.0).rem_euclid(360.0);
+        }
+    }
+
+    spread
+}
+
+/// 64-bit FNV-1a over the seed (little-endian), the key's UTF-8 bytes and the
+/// salt (little-endian, left out when zero). Unlike `DefaultHasher` its output
+/// is fixed, so a seed and key pick the same preferred hue or palette entry,
+/// and search the others in the same order, on every machine and build.
+fn stable_hash(seed: u64, key: &str, salt: u64) -> u64 {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const FNV_PRIME: u64 = 0x100000001b3;
+
+    let salt_bytes = salt.to_le_bytes();
+    let salt_bytes: &[u8] = if salt != 0 { &salt_bytes } else { &[] };
+    seed.to_le_bytes()
+        .iter()
+        .chain(key.as_bytes())
+        .chain(salt_bytes)
+        .fold(FNV_OFFSET_BASIS, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+        })
+}
+
+// Format version of a cache written by a newer build, if it is one
+fn newer_version(content: &str) -> Option<u64> {
+    let value: serde_json::Value = serde_json::from_str(content).ok()?;
+    value["version"]
+        .as_u64()
+        .filter(|&version| version > u64::from(CACHE_VERSION))
+}
+
+// Moves an unusable cache file aside, next to where it was
+fn quarantine(cache_path: &Path, error: &anyhow::Error) {
+    let quarantine_path = cache_path.with_extension(format!(
+        "json.corrupt.{}.{}",
+        unix_now(),
+        std::process::id()
+    ));
+    eprintln!(
+        "Warning: Color cache {} is unusable ({:#}); moving it to {} and starting fresh",
+        cache_path.display(),
+        error,
+        quarantine_path.display()
+    );
+    // Another process may have moved it already
+    let _ = fs::rename(cache_path, &quarantine_path);
+}
+
+// Upgrades a cache in the format of `from_version` to the next version
+fn migrate(value: &mut serde_json::Value, from_version: u32) -> Result<()> {
+    match from_version {
+        // Version 1 stored HSL hues and a redundant list of used hues; hues
+        // are OKLCH now, and tone changes regenerate colors from them
+        1 => {
+            if let Some(cache) = value.as_object_mut() {
+                cache.remove("used_hues");
+            }
+            if let Some(colors) = value
+                .get_mut("colors")
+                .and_then(|colors| colors.as_object_mut())
+            {
+                for cached_color in colors.values_mut() {
+                    let rgb: [u8; 3] = serde_json::from_value(cached_color["rgb"].clone())
+                        .context("Invalid cached color")?;
+                    cached_color["hue"] = Oklch::from_rgb(Rgb(rgb)).h.into();
+                }
+            }
+        }
+        _ => anyhow::bail!("No migration from color cache version {}", from_version),
+    }
+    Ok(())
+}
+
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// Holds an exclusive advisory lock on `<cache>.lock` until dropped
+fn lock_cache_file(cache_path: &Path) -> Result<fs::File> {
+    let lock_file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(cache_path.with_extension("json.lock"))
+        .context("Failed to open color cache lock file")?;
+    lock_file
+        .lock()
+        .context("Failed to lock color cache file")?;
+    Ok(lock_file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tmux::fake::pane;
+
+    // Panes side by side in window @1 of session "main"
+    fn window_panes(count: u32) -> Vec<TmuxPane> {
+        let cells: Vec<String> = (0..count)
+            .map(|i| format!("20x24,{},0,{}", i * 21, i + 1))
+            .collect();
+        let layout = format!("b25d,{}x24,0,0{{{}}}", count * 21 - 1, cells.join(","));
+        (0..count)
+            .map(|i| TmuxPane {
+                x: i * 21,
+                width: 20,
+                active: i == 0,
+                pane_index: i,
+                window_layout: layout.clone(),
+                ..pane(&format!("%{}", i + 1), "@1")
+            })
+            .collect()
+    }
+
+    // A cache coloring by adjacency, split tree and session families
+    fn caches_in_each_mode() -> Vec<ColorCache> {
+        let mut split_tree = ColorCache::new();
+        split_tree.set_split_tree(true);
+        let mut session_families = ColorCache::new();
+        session_families.set_session_families(true);
+        vec![ColorCache::new(), split_tree, session_families]
+    }
+
+    fn family_steps(cache: &ColorCache, panes: &[TmuxPane]) -> Vec<Option<u32>> {
+        panes
+            .iter()
+            .map(|pane| cache.colors[&cache.key_for(pane)].family.map(|slot| slot.step))
+            .collect()
+    }
+
+    #[test]
+    fn reroll_all_keeps_the_mode() {
+        let panes = window_panes(3);
+        for mut cache in caches_in_each_mode() {
+            cache.assign_pane_colors(&panes);
+            let families = cache.uses_families();
+
+            assert_eq!(cache.reroll_all(), 3);
+            cache.assign_pane_colors(&panes);
+
+            let mut steps = family_steps(&cache, &panes);
+            assert_eq!(steps.iter().all(Option::is_some), families);
+            steps.dedup();
+            assert_eq!(steps.len(), if families { 3 } else { 1 });
+            if cache.session_families {
+                let hue = cache.session_hues["$0"];
+                assert!(cache.colors.values().all(|c| c.family.unwrap().hue == hue));
+            }
+        }
+    }
+
+    #[test]
+    fn reroll_moves_one_pane_to_a_new_color() {
+        let panes = window_panes(3);
+        for mut cache in caches_in_each_mode() {
+            cache.assign_pane_colors(&panes);
+            let before = cache.colors.clone();
+            let key = cache.key_for(&panes[1]);
+
+            assert!(cache.reroll(&key, &panes));
+
+            let rerolled = &cache.colors[&key];
+            assert_ne!(rerolled.rgb, before[&key].rgb);
+            assert_eq!(
+                rerolled.family.map(|slot| slot.hue),
+                before[&key].family.map(|slot| slot.hue)
+            );
+            if let Some(slot) = rerolled.family {
+                assert_ne!(Some(slot), before[&key].family);
+            }
+            for pane in [&panes[0], &panes[2]] {
+                let other = cache.key_for(pane);
+                assert_eq!(cache.colors[&other], before[&other]);
+            }
+        }
+    }
+
+    #[test]
+    fn reroll_skips_pinned_and_unknown_panes() {
+        let panes = window_panes(2);
+        let mut cache = ColorCache::new();
+        cache.assign_pane_colors(&panes);
+        let key = cache.key_for(&panes[0]);
+        cache.set_pinned(&key, true);
+        let pinned = cache.colors[&key].clone();
+
+        assert!(!cache.reroll(&key, &panes));
+        assert!(!cache.reroll("@9:%9", &panes));
+        assert_eq!(cache.reroll_all(), 1);
+        assert_eq!(cache.colors[&key], pinned);
+    }
+
+    #[test]
+    fn split_pane_takes_its_color_from_its_parent() {
+        let mut panes = window_panes(3);
+        let mut cache = ColorCache::new();
+        cache.set_split_tree(true);
+        // A dark tone leaves room for lighter and darker steps
+        cache.set_tone(PaneTone {
+            lightness: 0.5,
+            chroma: 0.09,
+        });
+        cache.assign_pane_colors(&panes);
+        let before = cache.colors.clone();
+
+        // Split %3 top and bottom
+        let layout = "b25d,62x24,0,0{20x24,0,0,1,20x24,21,0,2,20x24,42,0[20x12,42,0,3,20x11,42,13,4]}";
+        let mut new_pane = panes[2].clone();
+        new_pane.id = "%4".to_string();
+        new_pane.y = 13;
+        new_pane.height = 11;
+        new_pane.pane_index = 3;
+        panes.push(new_pane);
+        for pane in &mut panes {
+            pane.window_layout = layout.to_string();
+            if pane.id == "%3" {
+                pane.height = 12;
+            }
+        }
+        cache.assign_pane_colors(&panes);
+
+        let parent = &cache.colors[&cache.key_for(&panes[2])];
+        let child = &cache.colors[&cache.key_for(&panes[3])];
+        assert_eq!(parent, &before[&cache.key_for(&panes[2])]);
+        assert!(hue_difference(parent.hue, child.hue).abs() < 0.5);
+        let lightnesses = cache.family_lightnesses();
+        let lightness = |cached_color: &CachedColor| {
+            lightnesses[cached_color.family.unwrap().step as usize % lightnesses.len()]
+        };
+        assert!(
+            ((lightness(child) - lightness(parent)).abs() - FAMILY_LIGHTNESS_STEP).abs() < 1e-4
+        );
+    }
+
+    #[test]
+    fn clashing_colors_without_a_better_one_stay_as_they_are() {
+        let panes = window_panes(4);
+        let mut cache = ColorCache::new();
+        // Some seeds leave room for a better color; this one does not
+        cache.startup_seed = 0;
+        cache.set_harmony(Some(Harmony::Monochrome));
+        cache.assign_pane_colors(&panes);
+        for (created_at, pane) in (1..).zip(&panes) {
+            let cached_color = cache.colors.get_mut(&cache.key_for(pane)).unwrap();
+            cached_color.created_at = created_at;
+        }
+        let before = cache.colors.clone();
+
+        cache.assign_pane_colors(&panes);
+
+        assert_eq!(cache.colors, before);
+    }
+
+    #[test]
+    fn seeded_colors_do_not_depend_on_pane_order() {
+        let panes = window_panes(4);
+        let mut reversed = panes.clone();
+        reversed.reverse();
+        for mode in 0..3 {
+            let mut forward = caches_in_each_mode().remove(mode);
+            let mut backward = caches_in_each_mode().remove(mode);
+            forward.set_seed(42);
+            backward.set_seed(42);
+
+            forward.assign_pane_colors(&panes);
+            backward.assign_pane_colors(&reversed);
+
+            for pane in &panes {
+                let key = forward.key_for(pane);
+                assert_eq!(forward.colors[&key].rgb, backward.colors[&key].rgb);
+            }
+        }
+    }
+
+    #[test]
+    fn seeded_neighbors_stay_distinct() {
+        let panes = window_panes(4);
+        // Seeds that used to leave touching panes ΔE 6.9 apart, and others
+        for seed in 0..20 {
+            let mut cache = ColorCache::new();
+            cache.set_palette(Some(crate::palette::find_palette("nord").unwrap()));
+            cache.set_seed(seed);
+            cache.assign_pane_colors(&panes);
+
+            let closest = panes
+                .windows(2)
+                .map(|pair| {
+                    let color = |pane: &TmuxPane| Rgb(cache.colors[&cache.key_for(pane)].rgb);
+                    delta_e(color(&pair[0]), color(&pair[1]))
+                })
+                .fold(f32::MAX, f32::min);
+            assert!(closest >= MIN_DELTA_E, "seed {} left panes {} apart", seed, closest);
+        }
+    }
+
+    // What a process holds after loading the given cache from disk
+    fn loaded(on_disk: &ColorCache) -> ColorCache {
+        let mut cache = ColorCache::parse(&serde_json::to_string(on_disk).unwrap()).unwrap();
+        cache.mark_loaded();
+        cache
+    }
+
+    fn cached_gray(level: u8, seen: u64) -> CachedColor {
+        let mut cached_color = CachedColor::new(Rgb([level, level, level]), 0.0, false);
+        cached_color.created_at = 100;
+        cached_color.last_seen = seen;
+        cached_color
+    }
+
+    fn sorted_keys(cache: &ColorCache) -> Vec<&str> {
+        let mut keys: Vec<&str> = cache.colors.keys().map(String::as_str).collect();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn merge_keeps_colors_added_concurrently() {
+        let mut on_disk = ColorCache::new();
+        on_disk.colors.insert("@1:%1".to_string(), cached_gray(100, 100));
+        let mut process = loaded(&on_disk);
+
+        process.colors.insert("@1:%2".to_string(), cached_gray(150, 200));
+        on_disk.colors.insert("@1:%3".to_string(), cached_gray(200, 200));
+        let merged = process.merged_with(loaded(&on_disk));
+
+        assert_eq!(sorted_keys(&merged), ["@1:%1", "@1:%2", "@1:%3"]);
+        assert_eq!(merged.colors["@1:%2"], process.colors["@1:%2"]);
+    }
+
+    #[test]
+    fn merge_drops_evicted_colors_unless_changed_since() {
+        let mut on_disk = ColorCache::new();
+        for (key, level) in [("@1:%1", 100), ("@1:%2", 150), ("@1:%3", 200)] {
+            on_disk.colors.insert(key.to_string(), cached_gray(level, 100));
+        }
+        let mut process = loaded(&on_disk);
+
+        process.colors.remove("@1:%2");
+        process.colors.remove("@1:%3");
+        on_disk.colors.insert("@1:%3".to_string(), cached_gray(250, 300));
+        let merged = process.merged_with(loaded(&on_disk));
+
+        assert_eq!(sorted_keys(&merged), ["@1:%1", "@1:%3"]);
+        assert_eq!(merged.colors["@1:%3"].rgb, [250, 250, 250]);
+    }
+
+    #[test]
+    fn merge_keeps_pins_from_either_side() {
+        let mut on_disk = ColorCache::new();
+        on_disk.colors.insert("@1:%1".to_string(), cached_gray(100, 100));
+        on_disk.colors.insert("@1:%2".to_string(), cached_gray(150, 100));
+        let mut process = loaded(&on_disk);
+
+        process.set_pinned("@1:%1", true);
+        on_disk.set_pinned("@1:%2", true);
+        let merged = process.merged_with(loaded(&on_disk));
+
+        assert!(merged.colors["@1:%1"].pinned);
+        assert!(merged.colors["@1:%2"].pinned);
+    }
+
+    #[test]
+    fn merge_of_a_render_only_moves_last_seen() {
+        let mut on_disk = ColorCache::new();
+        on_disk.colors.insert("@1:%1".to_string(), cached_gray(100, 100));
+        let mut process = loaded(&on_disk);
+
+        process.clean_missing_panes(&["@1:%1".to_string()]);
+        let mut recolored = cached_gray(200, 100);
+        recolored.pinned = true;
+        on_disk.colors.insert("@1:%1".to_string(), recolored);
+        let merged = process.merged_with(loaded(&on_disk));
+
+        let merged_color = &merged.colors["@1:%1"];
+        assert_eq!(merged_color.rgb, [200, 200, 200]);
+        assert!(merged_color.pinned);
+        assert_eq!(merged_color.last_seen, process.colors["@1:%1"].last_seen);
+    }
+
+    #[test]
+    fn merge_takes_the_settings_each_side_changed() {
+        let mut on_disk = ColorCache::new();
+        on_disk.colors.insert("@1:%1".to_string(), cached_gray(100, 100));
+        on_disk.colors.insert("@1:%2".to_string(), cached_gray(150, 100));
+        on_disk.set_pinned("@1:%2", true);
+        let mut process = loaded(&on_disk);
+
+        // This process turns darker and colors a new pane...
+        let tone = PaneTone {
+            lightness: 0.3,
+            ..PaneTone::default()
+        };
+        process.set_tone(tone);
+        process.colors.insert("@1:%3".to_string(), cached_gray(30, 200));
+        // ...while another one switched to deuteranopia and pinned a pane
+        on_disk.set_color_vision(Some(ColorVision::Deuteranopia));
+        on_disk.colors.insert("@1:%4".to_string(), cached_gray(200, 200));
+        on_disk.pin_color("@1:%5", Rgb([250, 250, 250]));
+        let merged = process.merged_with(loaded(&on_disk));
+
+        assert_eq!(merged.tone, tone);
+        assert_eq!(merged.color_vision, Some(ColorVision::Deuteranopia));
+        // Neither side's colors were generated under both settings
+        assert_eq!(sorted_keys(&merged), ["@1:%2", "@1:%5"]);
+    }
+
+    #[test]
+    fn merge_keeps_the_file_when_only_it_changed_settings() {
+        let mut on_disk = ColorCache::new();
+        on_disk.colors.insert("@1:%1".to_string(), cached_gray(100, 100));
+        let mut process = loaded(&on_disk);
+
+        process.colors.insert("@1:%2".to_string(), cached_gray(150, 200));
+        process.pin_color("@1:%3", Rgb([250, 250, 250]));
+        on_disk.set_color_vision(Some(ColorVision::Tritanopia));
+        on_disk.colors.insert("@1:%4".to_string(), cached_gray(200, 200));
+        let merged = process.merged_with(loaded(&on_disk));
+
+        assert_eq!(merged.color_vision, Some(ColorVision::Tritanopia));
+        assert_eq!(sorted_keys(&merged), ["@1:%3", "@1:%4"]);
+    }
+
+    #[test]
+    fn merge_treats_session_hues_like_colors() {
+        let mut on_disk = ColorCache::new();
+        on_disk.session_hues.insert("$1".to_string(), 10.0);
+        on_disk.session_hues.insert("$2".to_string(), 20.0);
+        for (key, session) in [("@1:%1", "$1"), ("@2:%2", "$2"), ("@3:%3", "$3")] {
+            let mut cached_color = cached_gray(100, 100);
+            cached_color.session = Some(session.to_string());
+            on_disk.colors.insert(key.to_string(), cached_color);
+        }
+        let mut process = loaded(&on_disk);
+
+        // Both sides gave the new session $3 a hue; this process's wins like
+        // the colors it derived from it would
+        process.session_hues.insert("$3".to_string(), 300.0);
+        on_disk.session_hues.insert("$3".to_string(), 200.0);
+        // A hue only the other side changed stays, one this side dropped goes
+        on_disk.session_hues.insert("$2".to_string(), 40.0);
+        process.session_hues.remove("$1");
+        let merged = process.merged_with(loaded(&on_disk));
+
+        assert_eq!(merged.session_hues.get("$1"), None);
+        assert_eq!(merged.session_hues["$2"], 40.0);
+        assert_eq!(merged.session_hues["$3"], 300.0);
+    }
+
+    #[test]
+    fn gc_spares_live_panes() {
+        let mut cache = ColorCache::new();
+        cache.set_retention(RetentionPolicy {
+            grace_period_secs: 60,
+            max_entries: 1,
+            evict_by: Eviction::Oldest,
+        });
+        for (key, created_at) in [("@1:%1", 100), ("@1:%2", 200), ("@1:%3", 300)] {
+            let mut cached_color = CachedColor::new(Rgb([200, 200, 200]), 0.0, false);
+            cached_color.created_at = created_at;
+            cached_color.last_seen = created_at;
+            cache.colors.insert(key.to_string(), cached_color);
+        }
+
+        assert_eq!(cache.gc(&["@1:%1".to_string()]), 2);
+        assert!(cache.colors.contains_key("@1:%1"));
+    }
+
+    // Smallest distance between any two hues on the wheel
+    fn min_hue_gap(hues: &[f32]) -> f32 {
+        let mut gap = f32::MAX;
+        for (i, &a) in hues.iter().enumerate() {
+            for &b in &hues[i + 1..] {
+                gap = gap.min(hue_difference(a, b).abs());
+            }
+        }
+        gap
+    }
+
+    #[test]
+    fn spread_hues_keeps_pins_and_settles() {
+        let hues = [(0.0, true), (10.0, false), (20.0, false), (30.0, false)];
+        let spread = spread_hues(&hues);
+
+        assert_eq!(spread[0], 0.0);
+        assert!((min_hue_gap(&spread) - 90.0).abs() < 0.5);
+
+        // Spreading what is already spread moves nothing
+        let again: Vec<(f32, bool)> = spread
+            .iter()
+            .zip(&hues)
+            .map(|(&hue, &(_, pinned))| (hue, pinned))
+            .collect();
+        for (before, after) in spread.iter().zip(spread_hues(&again)) {
+            assert!(hue_difference(*before, after).abs() < 0.5);
+        }
+    }
+
+    #[test]
+    fn rebalance_widens_gaps_and_leaves_pins() {
+        let mut cache = ColorCache::new();
+        let keys: Vec<String> = (1..=4).map(|i| format!("@1:%{}", i)).collect();
+        for (key, hue) in keys.iter().zip([100.0, 110.0, 120.0, 300.0]) {
+            let pinned = hue == 300.0;
+            let color = CachedColor::new(cache.color_for_hue(hue), hue, pinned);
+            cache.colors.insert(key.clone(), color);
+        }
+        let pinned = cache.colors["@1:%4"].clone();
+        let hues = |cache: &ColorCache| -> Vec<f32> {
+            keys.iter().map(|key| cache.colors[key].hue).collect()
+        };
+        let gap_before = min_hue_gap(&hues(&cache));
+
+        let rebalance = Rebalance { stability: 0.0 };
+        assert_eq!(cache.rebalance(&keys, &rebalance), 3);
+        assert_eq!(cache.colors["@1:%4"], pinned);
+        assert!(min_hue_gap(&hues(&cache)) > gap_before);
+
+        // A second run over the same panes changes nothing
+        let settled = hues(&cache);
+        cache.balanced_keys.clear();
+        assert_eq!(cache.rebalance(&keys, &rebalance), 0);
+        assert_eq!(hues(&cache), settled);
+    }
+
+    #[test]
+    fn renamed_session_keeps_its_hue() {
+        let mut cache = ColorCache::new();
+        cache.set_session_families(true);
+        cache.assign_pane_colors(&window_panes(2));
+        let hue = cache.session_hues["$0"];
+
+        // A pane split off after the session was renamed joins the family
+        let mut panes = window_panes(3);
+        for pane in &mut panes {
+            pane.session_name = "work".to_string();
+        }
+        cache.assign_pane_colors(&panes);
+
+        assert_eq!(cache.colors["@1:%3"].family.unwrap().hue, hue);
+        assert_eq!(cache.session_hues.keys().collect::<Vec<_>>(), ["$0"]);
+    }
+
+    #[test]
+    fn key_strategy_change_rekeys_cached_colors() {
+        let mut panes = window_panes(3);
+        panes[0].title = "editor".to_string();
+        panes[1].title = "logs".to_string();
+        let mut cache = ColorCache::new();
+        cache.assign_pane_colors(&panes);
+        let colors: Vec<[u8; 3]> = panes
+            .iter()
+            .map(|pane| cache.colors[&cache.key_for(pane)].rgb)
+            .collect();
+
+        cache.set_key_strategy(KeyStrategy::Position, &panes);
+        assert_eq!(sorted_keys(&cache), ["pos:main:0.0", "pos:main:0.1", "pos:main:0.2"]);
+
+        // Every pane keeps its color under the new keys
+        cache.set_key_strategy(KeyStrategy::Title, &panes);
+        assert_eq!(sorted_keys(&cache), ["@1:%3", "title:editor", "title:logs"]);
+        for (pane, rgb) in panes.iter().zip(colors) {
+            assert_eq!(cache.colors[&cache.key_for(pane)].rgb, rgb);
+        }
+    }
+
+    #[test]
+    fn untitled_panes_do_not_share_a_title_key() {
+        let panes = window_panes(3);
+        let mut cache = ColorCache::new();
+        cache.set_key_strategy(KeyStrategy::Title, &panes);
+        cache.assign_pane_colors(&panes);
+
+        assert_eq!(sorted_keys(&cache), ["@1:%1", "@1:%2", "@1:%3"]);
+    }
+
+    #[test]
+    fn deuteranopia_rejects_a_red_green_pair() {
+        let red = Rgb([200, 70, 50]);
+        let green = Rgb([90, 150, 50]);
+        let blue = Rgb([60, 110, 210]);
+        let used_colors = [(red, 1.0)];
+
+        // Apart for normal vision, alike without green cones
+        let mut cache = ColorCache::new();
+        assert_eq!(cache.pick_most_distinct(&[green, blue], 0, &used_colors), Some(0));
+        cache.set_color_vision(Some(ColorVision::Deuteranopia));
+        let seen = |rgb| ColorVision::Deuteranopia.simulate(rgb);
+        assert!(delta_e(seen(red), seen(green)) < MIN_DELTA_E);
+        assert_eq!(cache.pick_most_distinct(&[green, blue], 0, &used_colors), Some(1));
+    }
+
+    #[test]
+    fn pins_of_vanished_panes_expire_after_the_grace_period() {
+        let mut cache = ColorCache::new();
+        cache.set_retention(RetentionPolicy {
+            grace_period_secs: 60,
+            ..RetentionPolicy::default()
+        });
+        for key in ["@1:%1", "@1:%2", "@1:%3"] {
+            let mut cached_color = CachedColor::new(Rgb([200, 200, 200]), 0.0, true);
+            cached_color.created_at = 100;
+            cached_color.last_seen = 100;
+            cache.colors.insert(key.to_string(), cached_color);
+        }
+        cache.colors.get_mut("@1:%3").unwrap().last_seen = 1000;
+
+        // Only the live pane and the one still within its grace period stay
+        assert_eq!(cache.evict(1030, |key| key == "@1:%1"), 1);
+        assert_eq!(sorted_keys(&cache), ["@1:%1", "@1:%3"]);
+    }
+
+    // An empty directory of its own for each test touching files
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "kitty-pane-bg-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn quarantined_files(dir: &Path) -> usize {
+        fs::read_dir(dir)
+            .unwrap()
+            .filter(|entry| {
+                entry
+                    .as_ref()
+                    .unwrap()
+                    .file_name()
+                    .to_string_lossy()
+                    .contains(".corrupt.")
+            })
+            .count()
+    }
+
+    #[test]
+    fn v1_cache_is_migrated() {
+        let content = r##"{
+            "colors": {"@1:%1": {"rgb": [200, 80, 80], "hue": 0.0, "created_at": 100}},
+            "startup_seed": 7,
+            "used_hues": [0.0]
+        }"##;
+
+        let cache = ColorCache::parse(content).unwrap();
+
+        assert_eq!(cache.version, CACHE_VERSION);
+        let cached_color = &cache.colors["@1:%1"];
+        assert_eq!(cached_color.rgb, [200, 80, 80]);
+        assert!((cached_color.hue - Oklch::from_rgb(Rgb([200, 80, 80])).h).abs() < 1e-3);
+    }
+
+    #[test]
+    fn corrupt_cache_is_quarantined() {
+        let dir = scratch_dir("corrupt");
+        let path = dir.join("cache.json");
+        fs::write(&path, r#"{"colors": {"@1:%1": "#).unwrap();
+
+        assert!(ColorCache::read_from(&path).unwrap().is_none());
+        assert!(!path.exists());
+        assert_eq!(quarantined_files(&dir), 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn newer_cache_is_left_alone() {
+        let dir = scratch_dir("newer");
+        let path = dir.join("cache.json");
+        let content = format!(r#"{{"version": {}, "colors": {{}}}}"#, CACHE_VERSION + 1);
+        fs::write(&path, &content).unwrap();
+
+        let cache = ColorCache::read_from(&path).unwrap().unwrap();
+        assert!(cache.read_only && cache.colors.is_empty());
+        assert_eq!(fs::read_to_string(&path).unwrap(), content);
+        assert_eq!(quarantined_files(&dir), 0);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn unreadable_cache_is_quarantined() {
+        let dir = scratch_dir("not-utf8");
+        let path = dir.join("cache.json");
+        fs::write(&path, [b'{', 0xff, 0xfe, b'}']).unwrap();
+
+        assert!(ColorCache::read_from(&path).unwrap().is_none());
+        assert!(!path.exists());
+        assert_eq!(quarantined_files(&dir), 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
 }
@@ -0,0 +1,55 @@
+use crate::tmux::TmuxPane;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// How a pane is identified in the color cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyStrategy {
+    /// tmux window and pane ids (`@3:%12`). Reassigned when the tmux server
+    /// restarts or a pane is moved with join-pane/move-pane.
+    #[default]
+    Volatile,
+    /// Session name, window index and pane index
+    Position,
+    /// Pane title as set by the shell or `select-pane -T`, falling back to
+    /// the volatile ids for untitled panes
+    Title,
+    /// The `@pane-bg-key` user option, falling back to the volatile ids
+    /// for panes that do not set it
+    Option,
+}
+
+impl KeyStrategy {
+    pub fn key(self, pane: &TmuxPane) -> String {
+        match self {
+            KeyStrategy::Volatile => color_key(&pane.window_id, &pane.id),
+            KeyStrategy::Position => format!(
+                "pos:{}:{}.{}",
+                sanitize_id(&pane.session_name),
+                pane.window_index,
+                pane.pane_index
+            ),
+            KeyStrategy::Title => match sanitize_id(&pane.title) {
+                title if title.is_empty() => KeyStrategy::Volatile.key(pane),
+                title => format!("title:{}", title),
+            },
+            KeyStrategy::Option => match &pane.key_option {
+                Some(value) => format!("opt:{}", sanitize_id(value)),
+                None => KeyStrategy::Volatile.key(pane),
+            },
+        }
+    }
+}
+
+pub fn color_key(window_id: &str, pane_id: &str) -> String {
+    format!("{}:{}", sanitize_id(window_id), sanitize_id(pane_id))
+}
+
+fn sanitize_id(id: &str) -> String {
+    // Remove potentially problematic characters
+    id.chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, '%' | '@' | '-' | '_'))
+        .take(50) // Limit length
+        .collect()
+}
@@ -0,0 +1,79 @@
+use crate::color::ColorVision;
+use crate::color_cache::{Rebalance, RetentionPolicy};
+use crate::color_key::KeyStrategy;
+use crate::harmony::Harmony;
+use crate::schedule::Schedule;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+/// User settings read from `~/.config/kitty-pane-bg/config.json`.
+/// Every field is optional in the file; command line flags override it.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Minimum WCAG contrast ratio between pane colors and the terminal's
+    /// text colors. 0 disables the check.
+    pub min_contrast: f32,
+    /// Named palette to pick pane colors from instead of generated hues
+    pub palette: Option<String>,
+    /// Keep pane colors distinguishable for this color vision deficiency
+    pub cvd: Option<ColorVision>,
+    /// How panes are identified in the color cache
+    pub key_strategy: KeyStrategy,
+    /// Draw each session's colors from this scheme around a base hue
+    pub harmony: Option<Harmony>,
+    /// Color panes after the windows' split trees
+    pub split_tree: bool,
+    /// Give each session a hue, varied by window and pane
+    pub session_families: bool,
+    /// Re-spread visible hues evenly when panes come or go
+    pub rebalance: Option<Rebalance>,
+    /// Fixed seed colors are derived from, so the same panes get the same
+    /// colors on every machine. Random per cache when unset.
+    pub seed: Option<u64>,
+    /// Shifts pane colors over the day, applied when drawing
+    pub schedule: Option<Schedule>,
+    /// When colors of vanished panes are evicted from the cache
+    pub retention: RetentionPolicy,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            min_contrast: 4.5,
+            palette: None,
+            cvd: None,
+            key_strategy: KeyStrategy::default(),
+            harmony: None,
+            split_tree: false,
+            session_families: false,
+            rebalance: None,
+            seed: None,
+            schedule: None,
+            retention: RetentionPolicy::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn get_config_path() -> PathBuf {
+        dirs::config_dir()
+            .unwrap_or_else(|| PathBuf::from("/tmp"))
+            .join("kitty-pane-bg")
+            .join("config.json")
+    }
+
+    pub fn load() -> Result<Self> {
+        let config_path = Self::get_config_path();
+
+        if !config_path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(&config_path).context("Failed to read config file")?;
+
+        serde_json::from_str(&content).context("Failed to parse config file")
+    }
+}
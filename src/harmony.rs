@@ -0,0 +1,59 @@
+use crate::color::Oklch;
+use crate::color_cache::PaneTone;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+// Lightness offsets around the tone, so panes sharing a hue still differ
+const LIGHTNESS_STEPS: [f32; 3] = [0.0, -0.06, 0.06];
+const MONOCHROME_LIGHTNESS_STEPS: [f32; 5] = [0.0, -0.06, 0.06, -0.12, 0.12];
+
+/// Color scheme the panes of a session are drawn from, relative to a base
+/// hue derived from the session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Harmony {
+    /// Neighbouring hues within 60° of the base
+    Analogous,
+    /// The base hue and its opposite
+    Complementary,
+    /// Three hues spaced evenly around the wheel
+    Triadic,
+    /// The base hue and the two hues next to its opposite
+    SplitComplementary,
+    /// The base hue only, at different lightnesses
+    Monochrome,
+}
+
+impl Harmony {
+    /// Hue offsets in degrees from the base hue
+    fn hue_offsets(self) -> &'static [f32] {
+        match self {
+            Harmony::Analogous => &[0.0, 30.0, -30.0, 60.0, -60.0],
+            Harmony::Complementary => &[0.0, 180.0],
+            Harmony::Triadic => &[0.0, 120.0, 240.0],
+            Harmony::SplitComplementary => &[0.0, 150.0, 210.0],
+            Harmony::Monochrome => &[0.0],
+        }
+    }
+
+    /// Every color of the scheme at the tone's chroma, base hue first
+    pub fn colors(self, base_hue: f32, tone: &PaneTone) -> Vec<Oklch> {
+        let lightness_steps: &[f32] = match self {
+            Harmony::Monochrome => &MONOCHROME_LIGHTNESS_STEPS,
+            _ => &LIGHTNESS_STEPS,
+        };
+
+        lightness_steps
+            .iter()
+            .flat_map(|&step| {
+                self.hue_offsets().iter().map(move |&offset| {
+                    Oklch::new(
+                        (tone.lightness + step).clamp(0.2, 0.95),
+                        tone.chroma,
+                        (base_hue + offset).rem_euclid(360.0),
+                    )
+                })
+            })
+            .collect()
+    }
+}
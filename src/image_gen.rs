@@ -1,13 +1,79 @@
-use crate::color_cache::ColorCache;
+use crate::color::{ColorVision, TextContrast};
+use crate::color_cache::{ColorCache, Rebalance, RetentionPolicy};
+use crate::color_key::KeyStrategy;
+use crate::harmony::Harmony;
 use crate::kitty::WindowDimensions;
+use crate::minimap::{fill_rect, Minimap};
+use crate::palette::{find_palette, Palette};
+use crate::schedule::ColorShift;
+use crate::theme::{Theme, DEFAULT_BACKGROUND};
 use crate::tmux::TmuxPane;
 use anyhow::{Context, Result};
 use image::{Rgb, RgbImage};
 
+/// Optional extras applied on top of the basic pane layout
+#[derive(Debug, Default)]
+pub struct RenderOptions {
+    pub minimap: Option<Minimap>,
+    /// Every pane on the server, so colors of panes outside the drawn window are kept
+    pub live_panes: Vec<TmuxPane>,
+    pub text_contrast: Option<TextContrast>,
+    pub theme: Option<Theme>,
+    pub palette: Option<Palette>,
+    pub color_vision: Option<ColorVision>,
+    pub key_strategy: KeyStrategy,
+    pub harmony: Option<Harmony>,
+    pub seed: Option<u64>,
+    pub rebalance: Option<Rebalance>,
+    pub split_tree: bool,
+    pub session_families: bool,
+    pub color_shift: ColorShift,
+    pub retention: RetentionPolicy,
+}
+
+impl RenderOptions {
+    /// Applies the color settings to a loaded cache, so colors generated outside a
+    /// render come out the same as a render would pick them
+    pub fn configure(&self, color_cache: &mut ColorCache) {
+        color_cache.set_text_contrast(self.text_contrast.clone());
+        color_cache.set_palette(self.palette.clone());
+        color_cache.set_color_vision(self.color_vision);
+        color_cache.set_harmony(self.harmony);
+        color_cache.set_split_tree(self.split_tree);
+        color_cache.set_session_families(self.session_families);
+        color_cache.set_color_shift(self.color_shift);
+        if let Some(seed) = self.seed {
+            color_cache.set_seed(seed);
+        }
+        // Keep the cached tone when the theme is unknown rather than flipping back to pastels
+        if let Some(theme) = &self.theme {
+            color_cache.set_tone(theme.pane_tone());
+        }
+    }
+
+    /// Takes the palette, harmony and coloring mode the cache was last
+    /// rendered with instead of the configured ones, so commands run without
+    /// render flags work on the cached colors instead of clearing them
+    pub fn follow_cache(mut self, color_cache: &ColorCache) -> Result<Self> {
+        self.palette = color_cache
+            .palette
+            .as_deref()
+            .map(find_palette)
+            .transpose()?;
+        self.color_vision = color_cache.color_vision;
+        self.harmony = color_cache.harmony;
+        self.split_tree = color_cache.split_tree;
+        self.session_families = color_cache.session_families;
+        self.seed = None;
+        Ok(self)
+    }
+}
+
 pub async fn generate_pane_image(
     window_dims: &WindowDimensions,
     panes: &[TmuxPane],
     output_path: &str,
+    options: RenderOptions,
 ) -> Result<()> {
     // Input validation
     if window_dims.width == 0 || window_dims.height == 0 {
@@ -41,19 +107,50 @@ pub async fn generate_pane_image(
 
     // Load color cache with error handling
     let mut color_cache = ColorCache::load().context("Failed to load color cache")?;
+    options.configure(&mut color_cache);
+
+    // Move colors of every pane over to the configured keys before anything
+    // is looked up, not just those of the drawn panes
+    let mut visible_panes: Vec<TmuxPane> = panes.to_vec();
+    if let Some(minimap) = &options.minimap {
+        visible_panes.extend(minimap.panes().cloned());
+    }
+    let known_panes: Vec<TmuxPane> = visible_panes
+        .iter()
+        .chain(&options.live_panes)
+        .cloned()
+        .collect();
+    color_cache.set_key_strategy(options.key_strategy, &known_panes);
+    color_cache.set_retention(options.retention);
 
-    // Clean up colors for panes that no longer exist
-    let current_pane_keys: Vec<String> = panes
+    // Clean up colors for panes that no longer exist anywhere
+    let current_pane_keys: Vec<String> = visible_panes
+        .iter()
+        .map(|pane| color_cache.key_for(pane))
+        .collect();
+    let live_pane_keys: Vec<String> = options
+        .live_panes
         .iter()
-        .map(|p| format!("{}:{}", sanitize_id(&p.window_id), sanitize_id(&p.id)))
+        .map(|pane| color_cache.key_for(pane))
+        .chain(current_pane_keys.iter().cloned())
         .collect();
-    color_cache.clean_missing_panes(&current_pane_keys);
+    color_cache.clean_missing_panes(&live_pane_keys);
+
+    // Color new panes up front so neighbors are taken into account
+    color_cache.assign_pane_colors(&visible_panes);
+    if let Some(rebalance) = &options.rebalance {
+        color_cache.rebalance(&current_pane_keys, rebalance);
+    }
 
     // Create image buffer with bounds checking
     let mut image = RgbImage::new(window_dims.width, window_dims.height);
 
-    // Fill with background color
-    fill_background(&mut image);
+    // Fill with the terminal's own background color
+    let background_color = options
+        .theme
+        .map(|theme| theme.background)
+        .unwrap_or(DEFAULT_BACKGROUND);
+    fill_background(&mut image, background_color);
 
     if panes.is_empty() {
         save_image_safely(&image, output_path).await?;
@@ -67,6 +164,11 @@ pub async fn generate_pane_image(
         anyhow::bail!("Failed to draw any panes");
     }
 
+    // Overlays go on top of the pane layout
+    if let Some(minimap) = &options.minimap {
+        minimap.draw(&mut image, &mut color_cache);
+    }
+
     // Save color cache with error handling
     if let Err(e) = color_cache.save() {
         eprintln!("Warning: Failed to save color cache: {}", e);
@@ -78,9 +180,7 @@ pub async fn generate_pane_image(
     Ok(())
 }
 
-fn fill_background(image: &mut RgbImage) {
-    let background_color = Rgb([20, 20, 20]);
-
+fn fill_background(image: &mut RgbImage, background_color: Rgb<u8>) {
     // Use parallel processing for large images
     if image.width() * image.height() > 1_000_000 {
         use rayon::prelude::*;
@@ -132,8 +232,7 @@ fn draw_single_pane(
         ));
     }
 
-    let color_key = format!("{}:{}", sanitize_id(&pane.window_id), sanitize_id(&pane.id));
-    let rgb_color = color_cache.get_or_create_color(&color_key);
+    let rgb_color = color_cache.display_color(pane);
 
     // Convert coordinates with bounds checking
     let pixel_x = window_dims.char_to_pixel_x(pane.x);
@@ -169,16 +268,16 @@ fn draw_single_pane(
         return Ok(());
     }
 
-    // Draw the pane area with bounds checking
-    for y in pixel_y..end_y {
-        for x in pixel_x..end_x {
-            if x < window_dims.width && y < window_dims.height {
-                if let Some(pixel) = image.get_pixel_mut_checked(x, y) {
-                    *pixel = rgb_color;
-                }
-            }
-        }
-    }
+    // Same fill as the minimap and previews, so those show panes the way
+    // the background draws them
+    fill_rect(
+        image,
+        pixel_x,
+        pixel_y,
+        end_x - pixel_x,
+        end_y - pixel_y,
+        rgb_color,
+    );
 
     Ok(())
 }
@@ -241,14 +340,6 @@ async fn save_image_safely(image: &RgbImage, output_path: &str) -> Result<()> {
     Ok(())
 }
 
-fn sanitize_id(id: &str) -> String {
-    // Remove potentially problematic characters
-    id.chars()
-        .filter(|c| c.is_alphanumeric() || matches!(c, '%' | '@' | '-' | '_'))
-        .take(50) // Limit length
-        .collect()
-}
-
 pub fn generate_unique_filename(base_path: &str) -> String {
     use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -273,3 +364,29 @@ pub fn generate_unique_filename(base_path: &str) -> String {
     .to_string_lossy()
     .to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color_cache::CachedColor;
+
+    #[test]
+    fn following_the_cache_keeps_its_colors() {
+        let mut cache = ColorCache::new();
+        cache.set_palette(Some(find_palette("nord").unwrap()));
+        cache.set_split_tree(true);
+        cache.colors.insert(
+            "@0:%1".to_string(),
+            CachedColor::new(Rgb([0x88, 0xc0, 0xd0]), 200.0, false),
+        );
+
+        RenderOptions::default()
+            .follow_cache(&cache)
+            .unwrap()
+            .configure(&mut cache);
+
+        assert_eq!(cache.palette.as_deref(), Some("nord"));
+        assert!(cache.split_tree);
+        assert_eq!(cache.colors["@0:%1"].rgb, [0x88, 0xc0, 0xd0]);
+    }
+}
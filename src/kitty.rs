@@ -1,5 +1,7 @@
+use crate::tmux::{CommandRunner, TmuxClient};
 use anyhow::{Context, Result};
 use base64::{engine::general_purpose, Engine as _};
+use image::Rgb;
 use serde::{Deserialize, Serialize};
 use std::sync::{Arc, Mutex};
 use tokio::process::Command as AsyncCommand;
@@ -81,7 +83,10 @@ impl KittyRemoteInfo {
 
 /// Centralized function for all kitty remote control operations
 /// This function handles PID discovery, caching, validation, and fallback mechanisms
-pub async fn kitty_remote_call(args: &[&str]) -> Result<std::process::Output> {
+pub async fn kitty_remote_call<R: CommandRunner>(
+    tmux: &TmuxClient<R>,
+    args: &[&str],
+) -> Result<std::process::Output> {
     // First, try to get cached info
     if let Some(cached_info) = get_cached_kitty_info().await? {
         if let Ok(output) = try_kitty_call_with_socket(&cached_info.socket_path, args).await {
@@ -93,7 +98,7 @@ pub async fn kitty_remote_call(args: &[&str]) -> Result<std::process::Output> {
     }
 
     // Discover and cache new kitty info
-    let kitty_info = discover_and_cache_kitty_info().await?;
+    let kitty_info = discover_and_cache_kitty_info(tmux).await?;
     // Try with discovered info
     if let Some(info) = kitty_info {
         if let Ok(output) = try_kitty_call_with_socket(&info.socket_path, args).await {
@@ -101,16 +106,17 @@ pub async fn kitty_remote_call(args: &[&str]) -> Result<std::process::Output> {
         }
     }
 
-    panic!("Failed to execute kitty command: No valid kitty PID or socket found");
+    anyhow::bail!("Failed to execute kitty command: No valid kitty PID or socket found");
 }
 
 async fn get_cached_kitty_info() -> Result<Option<KittyRemoteInfo>> {
-    let cache = KITTY_CACHE.lock().unwrap();
-    if let Some(ref info) = *cache {
+    // Clone out of the lock so the guard is not held across the await below
+    let cached = KITTY_CACHE.lock().unwrap().clone();
+    if let Some(info) = cached {
         if info.is_valid() {
             // Validate that the PID and socket still exist
-            if validate_kitty_info(info).await {
-                return Ok(Some(info.clone()));
+            if validate_kitty_info(&info).await {
+                return Ok(Some(info));
             }
         }
     }
@@ -122,8 +128,10 @@ fn clear_kitty_cache() {
     *cache = None;
 }
 
-async fn discover_and_cache_kitty_info() -> Result<Option<KittyRemoteInfo>> {
-    if let Some(pid) = discover_kitty_pid().await? {
+async fn discover_and_cache_kitty_info<R: CommandRunner>(
+    tmux: &TmuxClient<R>,
+) -> Result<Option<KittyRemoteInfo>> {
+    if let Some(pid) = discover_kitty_pid(tmux).await? {
         let socket_path = format!("unix:/tmp/kitty-{}", pid);
 
         let info = KittyRemoteInfo {
@@ -162,7 +170,7 @@ async fn try_kitty_call_with_socket(
     args: &[&str],
 ) -> Result<std::process::Output> {
     let mut cmd = AsyncCommand::new("kitten");
-    cmd.args(&["@"]);
+    cmd.args(["@"]);
     cmd.arg("--to").arg(socket_path);
     cmd.args(args);
 
@@ -181,9 +189,11 @@ async fn try_kitty_call_with_socket(
     }
 }
 
-pub async fn get_kitty_window_info() -> Result<WindowDimensions> {
+pub async fn get_kitty_window_info<R: CommandRunner>(
+    tmux: &TmuxClient<R>,
+) -> Result<WindowDimensions> {
     // Try kitty remote control using centralized function
-    match try_kitty_remote_control().await {
+    match try_kitty_remote_control(tmux).await {
         Ok(dims) => return Ok(dims),
         Err(e) => {
             eprintln!("Warning: kitty remote control failed: {}", e);
@@ -195,19 +205,24 @@ pub async fn get_kitty_window_info() -> Result<WindowDimensions> {
     get_fallback_dimensions().await
 }
 
-async fn try_kitty_remote_control() -> Result<WindowDimensions> {
+async fn try_kitty_remote_control<R: CommandRunner>(
+    tmux: &TmuxClient<R>,
+) -> Result<WindowDimensions> {
     // Use centralized remote call function
-    let output = kitty_remote_call(&["ls"])
+    let output = kitty_remote_call(tmux, &["ls"])
         .await
         .context("Failed to get kitty window list")?;
 
     let windows: Vec<KittyWindow> =
         serde_json::from_slice(&output.stdout).context("Failed to parse kitty window info")?;
 
-    parse_kitty_windows(windows).await
+    parse_kitty_windows(tmux, windows).await
 }
 
-async fn parse_kitty_windows(windows: Vec<KittyWindow>) -> Result<WindowDimensions> {
+async fn parse_kitty_windows<R: CommandRunner>(
+    tmux: &TmuxClient<R>,
+    windows: Vec<KittyWindow>,
+) -> Result<WindowDimensions> {
     if windows.is_empty() {
         anyhow::bail!("No kitty windows found");
     }
@@ -225,7 +240,7 @@ async fn parse_kitty_windows(windows: Vec<KittyWindow>) -> Result<WindowDimensio
     let sub_window = &tab.windows[0];
 
     // Try to get more accurate cell dimensions using kitty's capabilities
-    let cell_dims = get_kitty_cell_dimensions().await?;
+    let cell_dims = get_kitty_cell_dimensions(tmux).await?;
 
     // Calculate total pixel dimensions
     let pixel_width = (sub_window.columns as f32 * cell_dims.0) as u32;
@@ -239,6 +254,70 @@ async fn parse_kitty_windows(windows: Vec<KittyWindow>) -> Result<WindowDimensio
     })
 }
 
+/// Colors of the kitty window as reported by `kitten @ get-colors`,
+/// or as written in a kitty theme file
+#[derive(Debug, Clone)]
+pub struct KittyColors {
+    pub foreground: Rgb<u8>,
+    pub background: Rgb<u8>,
+    pub selection_background: Option<Rgb<u8>>,
+    /// color0..color15, the ANSI palette
+    pub ansi: Vec<Rgb<u8>>,
+}
+
+pub async fn get_kitty_colors<R: CommandRunner>(tmux: &TmuxClient<R>) -> Result<KittyColors> {
+    let output = kitty_remote_call(tmux, &["get-colors"])
+        .await
+        .context("Failed to get kitty colors")?;
+
+    parse_kitty_colors(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Parses `name #rrggbb` lines, the format shared by `get-colors` and kitty
+/// theme files. Comments and non-color settings are ignored.
+pub fn parse_kitty_colors(output: &str) -> Result<KittyColors> {
+    let mut colors = std::collections::HashMap::new();
+    for line in output
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+    {
+        let mut parts = line.split_whitespace();
+        if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+            if let Some(rgb) = parse_hex_color(value) {
+                colors.insert(name.to_string(), rgb);
+            }
+        }
+    }
+
+    let foreground = *colors
+        .get("foreground")
+        .context("kitty did not report a foreground color")?;
+    let background = *colors
+        .get("background")
+        .context("kitty did not report a background color")?;
+    let ansi = (0..16)
+        .filter_map(|i| colors.get(&format!("color{}", i)).copied())
+        .collect();
+
+    Ok(KittyColors {
+        foreground,
+        background,
+        selection_background: colors.get("selection_background").copied(),
+        ansi,
+    })
+}
+
+/// Parses `#rrggbb` (or `rrggbb`) color values as used by kitty
+pub fn parse_hex_color(value: &str) -> Option<Rgb<u8>> {
+    let hex = value.trim().trim_start_matches('#');
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some(Rgb([channel(0)?, channel(2)?, channel(4)?]))
+}
+
 async fn get_fallback_dimensions() -> Result<WindowDimensions> {
     // Try to get terminal size using stty or tput
     let (cols, rows) = get_terminal_size().await?;
@@ -263,7 +342,7 @@ async fn get_terminal_size() -> Result<(u32, u32)> {
     if let Ok(output) = AsyncCommand::new("stty").arg("size").output().await {
         if output.status.success() {
             let output_str = String::from_utf8_lossy(&output.stdout);
-            let parts: Vec<&str> = output_str.trim().split_whitespace().collect();
+            let parts: Vec<&str> = output_str.split_whitespace().collect();
             if parts.len() == 2 {
                 if let (Ok(rows), Ok(cols)) = (parts[0].parse::<u32>(), parts[1].parse::<u32>()) {
                     return Ok((cols, rows));
@@ -295,9 +374,11 @@ async fn get_terminal_size() -> Result<(u32, u32)> {
     Ok((80, 24))
 }
 
-async fn get_kitty_cell_dimensions() -> Result<(f32, f32)> {
+async fn get_kitty_cell_dimensions<R: CommandRunner>(
+    tmux: &TmuxClient<R>,
+) -> Result<(f32, f32)> {
     // Try to get cell dimensions from kitty window info first
-    match kitty_remote_call(&["ls"]).await {
+    match kitty_remote_call(tmux, &["ls"]).await {
         Ok(output) if output.status.success() => {
             let window_info_str = String::from_utf8_lossy(&output.stdout);
             // Try to parse kitty window info for dimensions
@@ -322,12 +403,14 @@ async fn get_kitty_cell_dimensions() -> Result<(f32, f32)> {
 // Removed unused functions that contained blocking terminal queries
 
 #[allow(dead_code)]
-async fn get_estimated_window_size() -> Result<(u32, u32)> {
-    let dims = get_kitty_window_info().await?;
+async fn get_estimated_window_size<R: CommandRunner>(
+    tmux: &TmuxClient<R>,
+) -> Result<(u32, u32)> {
+    let dims = get_kitty_window_info(tmux).await?;
     Ok((dims.width, dims.height))
 }
 
-pub async fn check_kitty_setup() -> Result<()> {
+pub async fn check_kitty_setup<R: CommandRunner>(tmux: &TmuxClient<R>) -> Result<()> {
     println!("🔍 Checking terminal environment...");
 
     // Check if we're in a supported terminal
@@ -345,7 +428,7 @@ pub async fn check_kitty_setup() -> Result<()> {
         }
         None => {
             println!("� No cached kitty info, discovering...");
-            if let Some(info) = discover_and_cache_kitty_info().await? {
+            if let Some(info) = discover_and_cache_kitty_info(tmux).await? {
                 println!(
                     "✅ Discovered kitty info: PID {}, socket {}",
                     info.pid, info.socket_path
@@ -371,7 +454,7 @@ pub async fn check_kitty_setup() -> Result<()> {
     }
 
     // Test remote control capabilities using centralized function
-    match try_kitty_remote_control().await {
+    match try_kitty_remote_control(tmux).await {
         Ok(dims) => {
             println!(
                 "✅ Remote control working: {}x{} pixels",
@@ -415,10 +498,15 @@ pub async fn check_kitty_setup() -> Result<()> {
     println!();
     println!("🎨 Testing background setting capability...");
 
+    // tmux 3.3 and later drop passthrough escapes unless allowed
+    let passthrough = match tmux.option("allow-passthrough").await {
+        Ok(Some(value)) => value != "off",
+        Ok(None) | Err(_) => false,
+    };
     let test_methods = vec![
         ("Centralized remote control", true),
         ("ANSI escape sequences", true),
-        ("Tmux passthrough", in_tmux),
+        ("Tmux passthrough", in_tmux && passthrough),
     ];
 
     for (method, available) in test_methods {
@@ -435,14 +523,17 @@ pub async fn check_kitty_setup() -> Result<()> {
     Ok(())
 }
 
-pub async fn set_kitty_background(image_path: &str) -> Result<()> {
+pub async fn set_kitty_background<R: CommandRunner>(
+    tmux: &TmuxClient<R>,
+    image_path: &str,
+) -> Result<()> {
     // Validate image file exists and is readable
     if !std::path::Path::new(image_path).exists() {
         anyhow::bail!("Image file does not exist: {}", image_path);
     }
 
     // Try centralized remote control first
-    match kitty_remote_call(&["set-background-image", image_path]).await {
+    match kitty_remote_call(tmux, &["set-background-image", image_path]).await {
         Ok(output) if output.status.success() => return Ok(()),
         Ok(output) => {
             println!(
@@ -457,7 +548,7 @@ pub async fn set_kitty_background(image_path: &str) -> Result<()> {
 
     // Fallback methods for non-remote scenarios
     if std::env::var("TMUX").is_ok() {
-        set_background_tmux_passthrough(image_path).await?;
+        set_background_tmux_passthrough(tmux, image_path).await?;
         return Ok(());
     }
 
@@ -466,7 +557,10 @@ pub async fn set_kitty_background(image_path: &str) -> Result<()> {
     Ok(())
 }
 
-async fn set_background_tmux_passthrough(image_path: &str) -> Result<()> {
+async fn set_background_tmux_passthrough<R: CommandRunner>(
+    tmux: &TmuxClient<R>,
+    image_path: &str,
+) -> Result<()> {
     // Convert image to base64 for transmission
     let image_data = tokio::fs::read(image_path)
         .await
@@ -476,16 +570,9 @@ async fn set_background_tmux_passthrough(image_path: &str) -> Result<()> {
     // Use tmux passthrough to send background image
     let escape_seq = format!("\\ePtmux;\\e\\e]20;{}\\e\\e\\\\\\e\\\\", encoded);
 
-    let output = AsyncCommand::new("tmux")
-        .arg("run-shell")
-        .arg(format!("printf '{}'", escape_seq))
-        .output()
-        .await;
-
-    match output {
-        Ok(result) if result.status.success() => Ok(()),
-        _ => anyhow::bail!("Failed to set background via tmux passthrough"),
-    }
+    tmux.run_shell(&format!("printf '{}'", escape_seq))
+        .await
+        .context("Failed to set background via tmux passthrough")
 }
 
 async fn set_background_ansi(image_path: &str) -> Result<()> {
@@ -509,9 +596,9 @@ async fn set_background_ansi(image_path: &str) -> Result<()> {
     }
 }
 
-pub async fn clear_kitty_background() -> Result<()> {
+pub async fn clear_kitty_background<R: CommandRunner>(tmux: &TmuxClient<R>) -> Result<()> {
     // Try centralized remote control first
-    match kitty_remote_call(&["set-background-image", "none"]).await {
+    match kitty_remote_call(tmux, &["set-background-image", "none"]).await {
         Ok(output) if output.status.success() => return Ok(()),
         Ok(output) => {
             println!(
@@ -526,7 +613,7 @@ pub async fn clear_kitty_background() -> Result<()> {
 
     // Fallback methods for non-remote scenarios
     if std::env::var("TMUX").is_ok() {
-        clear_background_tmux_passthrough().await?;
+        clear_background_tmux_passthrough(tmux).await?;
         return Ok(());
     }
 
@@ -536,19 +623,12 @@ pub async fn clear_kitty_background() -> Result<()> {
 }
 
 #[allow(dead_code)]
-async fn clear_background_tmux_passthrough() -> Result<()> {
+async fn clear_background_tmux_passthrough<R: CommandRunner>(tmux: &TmuxClient<R>) -> Result<()> {
     let escape_seq = "\\ePtmux;\\e\\e]20;\\e\\e\\\\\\e\\\\";
 
-    let output = AsyncCommand::new("tmux")
-        .arg("run-shell")
-        .arg(format!("printf '{}'", escape_seq))
-        .output()
-        .await;
-
-    match output {
-        Ok(result) if result.status.success() => Ok(()),
-        _ => anyhow::bail!("Failed to clear background via tmux passthrough"),
-    }
+    tmux.run_shell(&format!("printf '{}'", escape_seq))
+        .await
+        .context("Failed to clear background via tmux passthrough")
 }
 
 #[allow(dead_code)]
@@ -569,7 +649,7 @@ async fn clear_background_ansi() -> Result<()> {
 
 use std::collections::HashSet;
 
-async fn discover_kitty_pid() -> Result<Option<String>> {
+async fn discover_kitty_pid<R: CommandRunner>(tmux: &TmuxClient<R>) -> Result<Option<String>> {
     // First try the environment variable
     if let Ok(kitty_pid) = std::env::var("KITTY_PID") {
         // Verify this PID actually points to a kitty process
@@ -590,7 +670,7 @@ async fn discover_kitty_pid() -> Result<Option<String>> {
     // If we're in tmux, get the tmux client PID and walk up the process tree
     if let Ok(tmux_info) = std::env::var("TMUX") {
         println!("🔍 Searching for kitty PID via tmux process tree...");
-        match get_tmux_client_pid(&tmux_info).await {
+        match get_tmux_client_pid(tmux, &tmux_info).await {
             Ok(Some(client_pid)) => {
                 println!("📋 Found tmux client PID: {}", client_pid);
                 match find_kitty_in_process_tree(client_pid).await {
@@ -636,51 +716,31 @@ async fn discover_kitty_pid() -> Result<Option<String>> {
     }
 }
 
-async fn get_tmux_client_pid(tmux_info: &str) -> Result<Option<u32>> {
+async fn get_tmux_client_pid<R: CommandRunner>(
+    tmux: &TmuxClient<R>,
+    tmux_info: &str,
+) -> Result<Option<u32>> {
     // TMUX variable format: /tmp/tmux-1000/default,12345,0
-    // Extract the session info to find our client
+    // (socket path, server pid, session id without its '$')
     let parts: Vec<&str> = tmux_info.split(',').collect();
-    if parts.len() < 2 {
+    if parts.len() < 3 {
         return Ok(None);
     }
 
     // Try to get our specific session's client
-    let session_id = parts[1];
+    let session_id = format!("${}", parts[2]);
 
     // Get all tmux clients and find ours by session
-    let output = AsyncCommand::new("tmux")
-        .args(&["list-clients", "-F", "#{client_pid} #{session_id}"])
-        .output()
-        .await
-        .context("Failed to get tmux clients")?;
-
-    if !output.status.success() {
+    let Ok(clients) = tmux.clients().await else {
         return Ok(None);
-    }
-
-    let output_str = String::from_utf8_lossy(&output.stdout);
-    for line in output_str.lines() {
-        let client_parts: Vec<&str> = line.trim().split_whitespace().collect();
-        if client_parts.len() >= 2 {
-            if client_parts[1] == session_id {
-                if let Ok(pid) = client_parts[0].parse::<u32>() {
-                    return Ok(Some(pid));
-                }
-            }
-        }
-    }
+    };
 
     // Fallback: use any client PID if we can't match by session
-    for line in output_str.lines() {
-        let client_parts: Vec<&str> = line.trim().split_whitespace().collect();
-        if !client_parts.is_empty() {
-            if let Ok(pid) = client_parts[0].parse::<u32>() {
-                return Ok(Some(pid));
-            }
-        }
-    }
-
-    Ok(None)
+    Ok(clients
+        .iter()
+        .find(|client| client.session_id == session_id)
+        .or(clients.first())
+        .map(|client| client.pid))
 }
 
 async fn find_kitty_in_process_tree(start_pid: u32) -> Result<Option<u32>> {
@@ -718,7 +778,7 @@ async fn get_parent_pid(pid: u32) -> Result<Option<u32>> {
             // We need to handle the case where comm might contain spaces or parentheses
             if let Some(last_paren) = content.rfind(')') {
                 let after_comm = &content[last_paren + 1..];
-                let parts: Vec<&str> = after_comm.trim().split_whitespace().collect();
+                let parts: Vec<&str> = after_comm.split_whitespace().collect();
                 if parts.len() >= 2 {
                     if let Ok(ppid) = parts[1].parse::<u32>() {
                         return Ok(if ppid <= 1 { None } else { Some(ppid) });
@@ -757,7 +817,7 @@ async fn is_kitty_process(pid: &str) -> bool {
 async fn find_any_kitty_parent() -> Result<Option<String>> {
     // Last resort: find any kitty process that could be our terminal
     let output = AsyncCommand::new("pgrep")
-        .args(&["-f", "kitty"])
+        .args(["-f", "kitty"])
         .output()
         .await
         .context("Failed to search for kitty processes")?;
@@ -785,7 +845,7 @@ async fn is_potential_parent_kitty(kitty_pid: u32) -> bool {
     // Check if this kitty process has child processes that could lead to us
     // This is a heuristic - a kitty terminal should have shell children
     let children_output = AsyncCommand::new("pgrep")
-        .args(&["-P", &kitty_pid.to_string()])
+        .args(["-P", &kitty_pid.to_string()])
         .output()
         .await;
 
@@ -837,3 +897,105 @@ fn parse_kitty_window_dimensions(window_info: &str) -> Result<(f32, f32)> {
 
     Err(anyhow::anyhow!("Could not parse kitty window dimensions"))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tmux::fake::ScriptedRunner;
+
+    #[tokio::test]
+    async fn client_pid_is_found_by_session() {
+        let runner = ScriptedRunner::new().respond(&["list-clients"], "111\t$0\n222\t$2\n");
+        let tmux = TmuxClient::with_runner(runner);
+
+        let pid = get_tmux_client_pid(&tmux, "/tmp/tmux-1000/default,4242,2")
+            .await
+            .unwrap();
+
+        assert_eq!(pid, Some(222));
+    }
+
+    #[tokio::test]
+    async fn client_pid_falls_back_to_any_client() {
+        let runner = ScriptedRunner::new().respond(&["list-clients"], "111\t$0\n222\t$2\n");
+        let tmux = TmuxClient::with_runner(runner);
+
+        let pid = get_tmux_client_pid(&tmux, "/tmp/tmux-1000/default,4242,7")
+            .await
+            .unwrap();
+
+        assert_eq!(pid, Some(111));
+    }
+
+    #[tokio::test]
+    async fn client_pid_is_none_without_a_server() {
+        let runner = ScriptedRunner::new().fail(&["list-clients"], "no server running");
+        let tmux = TmuxClient::with_runner(runner);
+
+        let pid = get_tmux_client_pid(&tmux, "/tmp/tmux-1000/default,4242,0")
+            .await
+            .unwrap();
+
+        assert_eq!(pid, None);
+    }
+
+    #[tokio::test]
+    async fn passthrough_goes_through_run_shell() {
+        let tmux = TmuxClient::with_runner(ScriptedRunner::new().respond(&["run-shell"], ""));
+
+        clear_background_tmux_passthrough(&tmux).await.unwrap();
+
+        let calls = tmux.runner().calls();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0][0], "run-shell");
+        assert!(calls[0][1].starts_with("printf '\\ePtmux;"));
+    }
+
+    #[test]
+    fn hex_colors_need_six_hex_digits() {
+        assert_eq!(parse_hex_color("#1e1e2e"), Some(Rgb([0x1e, 0x1e, 0x2e])));
+        assert_eq!(parse_hex_color(" CDD6F4 "), Some(Rgb([0xcd, 0xd6, 0xf4])));
+        assert_eq!(parse_hex_color("#fff"), None);
+        assert_eq!(parse_hex_color("#1e1e2e00"), None);
+        assert_eq!(parse_hex_color("#gg0000"), None);
+        assert_eq!(parse_hex_color("#\u{e9}\u{e9}\u{e9}"), None);
+        assert_eq!(parse_hex_color("red"), None);
+    }
+
+    #[test]
+    fn theme_comments_blank_lines_and_bad_values_are_skipped() {
+        let theme = "\
+# vim:ft=kitty
+## name: Test
+
+foreground #cdd6f4
+background #1e1e2e
+  # color0 #000000
+color1     #f38ba8
+color2 #zzzzzz
+cursor none
+selection_background #f5e0dc
+";
+        let colors = parse_kitty_colors(theme).unwrap();
+
+        assert_eq!(colors.foreground, Rgb([0xcd, 0xd6, 0xf4]));
+        assert_eq!(colors.background, Rgb([0x1e, 0x1e, 0x2e]));
+        assert_eq!(colors.selection_background, Some(Rgb([0xf5, 0xe0, 0xdc])));
+        assert_eq!(colors.ansi, [Rgb([0xf3, 0x8b, 0xa8])]);
+    }
+
+    #[test]
+    fn theme_with_missing_slots() {
+        // Missing ANSI slots are left out, in order
+        let colors = parse_kitty_colors(
+            "foreground #ffffff\nbackground #000000\ncolor4 #0000ff\ncolor2 #00ff00\n",
+        )
+        .unwrap();
+        assert_eq!(colors.ansi, [Rgb([0, 255, 0]), Rgb([0, 0, 255])]);
+        assert_eq!(colors.selection_background, None);
+
+        // Without a background there is nothing to derive a tone from
+        let error = parse_kitty_colors("foreground #ffffff\ncolor1 #ff0000\n").unwrap_err();
+        assert!(error.to_string().contains("background"));
+    }
+}
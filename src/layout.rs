@@ -0,0 +1,143 @@
+use anyhow::{Context, Result};
+
+/// A node of tmux's split tree as described by `#{window_layout}`
+#[derive(Debug, Clone, PartialEq)]
+pub enum LayoutCell {
+    /// A pane, by its tmux id (`%3`)
+    Pane(String),
+    /// Cells created by splitting one cell, left to right or top to bottom
+    Split(Vec<LayoutCell>),
+}
+
+/// Parses a layout string like `bb62,159x48,0,0{79x48,0,0,1,79x48,80,0,2}`.
+/// Each cell is `WxH,X,Y` followed by a pane number, or by its children in
+/// `{}` (side by side) or `[]` (stacked).
+pub fn parse_window_layout(layout: &str) -> Result<LayoutCell> {
+    let (_checksum, cells) = layout
+        .split_once(',')
+        .context("Window layout has no checksum")?;
+
+    let mut parser = LayoutParser {
+        input: cells.as_bytes(),
+        position: 0,
+    };
+    let cell = parser.cell()?;
+    if parser.position != parser.input.len() {
+        anyhow::bail!("Trailing characters in window layout '{}'", layout);
+    }
+
+    Ok(cell)
+}
+
+struct LayoutParser<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl LayoutParser<'_> {
+    fn cell(&mut self) -> Result<LayoutCell> {
+        // Geometry: WxH,X,Y
+        self.number()?;
+        self.expect(b'x')?;
+        self.number()?;
+        self.expect(b',')?;
+        self.number()?;
+        self.expect(b',')?;
+        self.number()?;
+
+        match self.peek() {
+            Some(b',') => {
+                self.position += 1;
+                Ok(LayoutCell::Pane(format!("%{}", self.number()?)))
+            }
+            Some(open @ (b'{' | b'[')) => {
+                let close = if open == b'{' { b'}' } else { b']' };
+                self.position += 1;
+
+                let mut children = vec![self.cell()?];
+                while self.peek() == Some(b',') {
+                    self.position += 1;
+                    children.push(self.cell()?);
+                }
+                self.expect(close)?;
+
+                Ok(LayoutCell::Split(children))
+            }
+            _ => anyhow::bail!("Unexpected end of layout cell at {}", self.position),
+        }
+    }
+
+    fn number(&mut self) -> Result<u32> {
+        let start = self.position;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.position += 1;
+        }
+
+        std::str::from_utf8(&self.input[start..self.position])
+            .ok()
+            .and_then(|digits| digits.parse().ok())
+            .with_context(|| format!("Expected a number in window layout at {}", start))
+    }
+
+    fn expect(&mut self, expected: u8) -> Result<()> {
+        if self.peek() != Some(expected) {
+            anyhow::bail!(
+                "Expected '{}' in window layout at {}",
+                expected as char,
+                self.position
+            );
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.position).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pane(id: &str) -> LayoutCell {
+        LayoutCell::Pane(id.to_string())
+    }
+
+    #[test]
+    fn single_pane() {
+        assert_eq!(parse_window_layout("b25d,80x24,0,0,2").unwrap(), pane("%2"));
+    }
+
+    #[test]
+    fn nested_splits() {
+        let layout = "5b8e,159x48,0,0{79x48,0,0,1,79x48,80,0[79x24,80,0,2,79x23,80,25{39x23,80,25,3,39x23,120,25,4}]}";
+
+        assert_eq!(
+            parse_window_layout(layout).unwrap(),
+            LayoutCell::Split(vec![
+                pane("%1"),
+                LayoutCell::Split(vec![
+                    pane("%2"),
+                    LayoutCell::Split(vec![pane("%3"), pane("%4")]),
+                ]),
+            ])
+        );
+    }
+
+    #[test]
+    fn trailing_characters_are_rejected() {
+        assert!(parse_window_layout("b25d,80x24,0,0,2 ").is_err());
+        assert!(parse_window_layout("b25d,80x24,0,0{40x24,0,0,1,39x24,41,0,2}}").is_err());
+    }
+
+    #[test]
+    fn truncated_layouts_are_rejected() {
+        assert!(parse_window_layout("").is_err());
+        assert!(parse_window_layout("b25d").is_err());
+        assert!(parse_window_layout("b25d,80x24,0").is_err());
+        assert!(parse_window_layout("b25d,80x24,0,0,").is_err());
+        assert!(parse_window_layout("b25d,80x24,0,0{40x24,0,0,1,39x24,41,0,2").is_err());
+        assert!(parse_window_layout("b25d,80x24,0,0[40x24,0,0,1,39x24,41,0,2}").is_err());
+    }
+}
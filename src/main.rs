@@ -1,25 +1,111 @@
+mod adjacency;
+mod cache_export;
+mod cache_table;
+mod color;
 mod color_cache;
+mod color_key;
+mod config;
+mod harmony;
 mod image_gen;
 mod kitty;
+mod layout;
+mod minimap;
+mod palette;
+mod preview;
+mod schedule;
+mod theme;
 mod tmux;
 
 use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
-use color_cache::ColorCache;
-use image_gen::{generate_pane_image, generate_unique_filename};
+use cache_export::{MergeStrategy, PortableCache};
+use cache_table::{cache_entries, filter_entries, print_table, sort_entries, ShowSort};
+use clap::{Args, Parser, Subcommand};
+use color::{ansi_swatch, delta_e, named_color, ColorVision, TextContrast};
+use color_cache::{ColorCache, Rebalance};
+use color_key::KeyStrategy;
+use config::Config;
+use harmony::Harmony;
+use image_gen::{generate_pane_image, generate_unique_filename, RenderOptions};
 use kitty::{
-    check_kitty_setup, clear_kitty_background, get_kitty_window_info, set_kitty_background,
+    check_kitty_setup, clear_kitty_background, get_kitty_colors, get_kitty_window_info,
+    parse_hex_color, set_kitty_background,
+};
+use minimap::{Corner, Minimap};
+use palette::{all_palettes, find_palette, import_kitty_theme};
+use preview::{display_inline, render_layout, render_swatches};
+use schedule::TimeOfDay;
+use std::collections::HashMap;
+use std::time::Duration;
+use theme::Theme;
+use tmux::{
+    install_tmux_hooks, resolve_socket_path, set_server_target, CommandRunner, ControlClient,
+    ControlEvent, PaneScope, ServerTarget, TmuxClient,
 };
-use tmux::{check_tmux_session, get_current_window_panes, install_tmux_hooks};
 
 #[derive(Parser)]
 #[command(name = "kitty-pane-bg")]
 #[command(about = "Generate pane background images using kitty and tmux")]
 struct Cli {
+    /// Socket path of the tmux server to use, like tmux -S
+    #[arg(short = 'S', long, global = true, value_name = "PATH")]
+    socket_path: Option<String>,
+    /// Socket name of the tmux server to use, like tmux -L
+    #[arg(
+        short = 'L',
+        long,
+        global = true,
+        value_name = "NAME",
+        conflicts_with = "socket_path"
+    )]
+    socket_name: Option<String>,
     #[command(subcommand)]
     command: Commands,
 }
 
+/// Options shared by every command that renders a background
+#[derive(Args, Default)]
+struct RenderArgs {
+    /// Use all panes across sessions (default: current window only)
+    #[arg(short, long)]
+    all_panes: bool,
+    /// Draw a thumbnail grid of every window in the session in this corner
+    #[arg(long, value_enum)]
+    minimap: Option<Corner>,
+    /// Minimum WCAG contrast ratio against kitty's text colors (0 disables)
+    #[arg(long, value_name = "RATIO")]
+    min_contrast: Option<f32>,
+    /// Pick pane colors from a named palette (see 'palette list')
+    #[arg(long, value_name = "NAME")]
+    palette: Option<String>,
+    /// Keep pane colors distinguishable for a color vision deficiency
+    #[arg(long, value_enum)]
+    cvd: Option<ColorVision>,
+    /// How panes are identified in the color cache
+    #[arg(long, value_enum)]
+    key_strategy: Option<KeyStrategy>,
+    /// Draw each session's pane colors from a harmony around a base hue
+    #[arg(long, value_enum)]
+    harmony: Option<Harmony>,
+    /// Color panes after how each window was split: each pane takes its own
+    /// lightness of the window's hue, turned a little per nested split
+    #[arg(long)]
+    split_tree: bool,
+    /// Give each session its own hue, with windows varying in saturation and
+    /// panes in lightness
+    #[arg(long)]
+    session_families: bool,
+    /// Re-spread hues evenly when panes come or go; STABILITY (0-1, default
+    /// 0.5) limits how far existing colors move
+    #[arg(long, value_name = "STABILITY", num_args = 0..=1, default_missing_value = "0.5")]
+    rebalance: Option<f32>,
+    /// Derive colors from this seed so the same panes get the same colors everywhere
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Apply the configured schedule as if it were this time of day
+    #[arg(long, value_name = "HH:MM")]
+    at: Option<TimeOfDay>,
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Generate background image based on current pane layout
@@ -27,30 +113,40 @@ enum Commands {
         /// Output image path
         #[arg(short, long, default_value = "pane_bg.png")]
         output: String,
-        /// Use all panes across sessions (default: current window only)
-        #[arg(short, long)]
-        all_panes: bool,
+        #[command(flatten)]
+        render: RenderArgs,
     },
     /// Generate and automatically set as kitty background
     SetBackground {
-        /// Use all panes across sessions (default: current window only)
-        #[arg(short, long)]
-        all_panes: bool,
+        #[command(flatten)]
+        render: RenderArgs,
         /// Keep the generated image file (default: delete after setting)
         #[arg(long)]
         keep_file: bool,
     },
     /// Alias for set-background - quickly generate and set as kitty background
     Auto {
-        /// Use all panes across sessions (default: current window only)
-        #[arg(short, long)]
-        all_panes: bool,
+        #[command(flatten)]
+        render: RenderArgs,
         /// Keep the generated image file (default: delete after setting)
         #[arg(long)]
         keep_file: bool,
     },
     /// Install tmux hooks
     InstallHooks,
+    /// Stay attached to tmux in control mode and set the background whenever
+    /// the layout, window or session changes, instead of installing hooks
+    Daemon {
+        #[command(flatten)]
+        render: RenderArgs,
+        /// Session to follow (default: the most recently used one, switching
+        /// along with other clients)
+        #[arg(short = 't', long)]
+        session: Option<String>,
+        /// Keep the generated image files (default: delete after setting)
+        #[arg(long)]
+        keep_file: bool,
+    },
     /// Check if running in tmux and kitty
     Check,
     /// Clear kitty background
@@ -60,169 +156,171 @@ enum Commands {
         #[command(subcommand)]
         action: CacheCommands,
     },
+    /// Manage pane color palettes
+    Palette {
+        #[command(subcommand)]
+        action: PaletteCommands,
+    },
 }
 
 #[derive(Subcommand)]
 enum CacheCommands {
-    /// Show current color cache
-    Show,
+    /// Show cached colors next to the live panes they belong to
+    Show {
+        /// Row order
+        #[arg(long, value_enum, default_value = "key")]
+        sort: ShowSort,
+        /// Only colors of panes in this session
+        #[arg(long)]
+        session: Option<String>,
+        /// Only colors of panes that currently exist
+        #[arg(long)]
+        live: bool,
+        /// Only pinned colors
+        #[arg(long)]
+        pinned: bool,
+        /// Print entries as JSON
+        #[arg(long)]
+        json: bool,
+    },
     /// Clear all cached colors
     Clear,
     /// Remove specific pane color
     Remove { pane_id: String },
+    /// Pin a specific color to a pane
+    Set {
+        /// Pane id (e.g. %3) or cache key (e.g. @1:%3)
+        pane: String,
+        /// Color as #rrggbb or a name such as teal
+        color: String,
+    },
+    /// Protect a pane's color from rerolls and recoloring
+    Lock { pane: String },
+    /// Allow a pane's color to change again
+    Unlock { pane: String },
+    /// Give unpinned panes new colors
+    Reroll {
+        /// Pane id or cache key to reroll
+        #[arg(required_unless_present = "all")]
+        pane: Option<String>,
+        /// Reroll every unpinned color with a new seed
+        #[arg(long, conflicts_with = "pane")]
+        all: bool,
+    },
+    /// Write cached colors in a portable form for sharing across machines
+    Export {
+        /// Output file (default: stdout)
+        #[arg(short, long)]
+        output: Option<String>,
+        /// Only export pinned colors; session hues are always exported
+        #[arg(long)]
+        pinned_only: bool,
+    },
+    /// Merge colors written by 'cache export' into the cache
+    Import {
+        /// File written by 'cache export'
+        file: String,
+        /// How to resolve keys that already have a local color
+        #[arg(long, value_enum, default_value = "keep")]
+        merge: MergeStrategy,
+    },
+    /// List the color caches of every tmux server seen
+    Servers {
+        /// Delete the caches of servers that are no longer running
+        #[arg(long)]
+        prune: bool,
+    },
+    /// Evict colors of vanished panes per the retention policy
+    Gc {
+        /// Only report what would be evicted
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Show cached colors inline, optionally next to how they appear with a
+    /// color vision deficiency
+    Preview {
+        /// Color vision deficiency to simulate
+        #[arg(long, value_enum)]
+        simulate: Option<ColorVision>,
+        /// Only print the legend, for terminals without the kitty graphics protocol
+        #[arg(long)]
+        no_image: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum PaletteCommands {
+    /// List available palettes
+    List,
+    /// Show the current window colored from a palette, without saving anything
+    Preview {
+        /// Palette name (see 'palette list')
+        name: String,
+        /// Only print the legend, for terminals without the kitty graphics protocol
+        #[arg(long)]
+        no_image: bool,
+    },
+    /// Derive a palette from a kitty theme file
+    Import {
+        /// Path to a kitty theme (.conf) file
+        theme: String,
+        /// Palette name (default: the theme file name)
+        #[arg(long)]
+        name: Option<String>,
+    },
+}
+
+impl Commands {
+    // The cache file depends on the tmux server, which can take a tmux call to
+    // find out, so only commands that use the cache look it up
+    fn uses_color_cache(&self) -> bool {
+        match self {
+            Commands::InstallHooks | Commands::Check | Commands::Clear => false,
+            Commands::Palette { action } => matches!(action, PaletteCommands::Preview { .. }),
+            _ => true,
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
 
+    if let Some(path) = cli.socket_path {
+        set_server_target(ServerTarget::SocketPath(path));
+    } else if let Some(name) = cli.socket_name {
+        set_server_target(ServerTarget::SocketName(name));
+    }
+    let tmux = TmuxClient::new();
+    if cli.command.uses_color_cache() {
+        color_cache::set_server(resolve_socket_path(&tmux).await);
+    }
+
     match cli.command {
-        Commands::Generate { output, all_panes } => {
+        Commands::Generate { output, render } => {
             // Check if we're in a tmux session
-            if !check_tmux_session().await? {
+            if !tmux.in_session().await {
                 anyhow::bail!("Not running in a tmux session. Please start tmux first.");
             }
 
-            println!("Getting kitty window information...");
-            let window_dims = get_kitty_window_info().await?;
-            println!(
-                "Window dimensions: {}x{} (cell: {:.1}x{:.1})",
-                window_dims.width,
-                window_dims.height,
-                window_dims.cell_width,
-                window_dims.cell_height
-            );
-
-            println!("Getting tmux pane information...");
-            let panes = if all_panes {
-                tmux::get_tmux_panes().await?
-            } else {
-                get_current_window_panes().await?
-            };
-
-            println!("Found {} panes", panes.len());
-
-            if panes.is_empty() {
-                println!("No tmux panes found. Creating a solid color background.");
-            }
-
-            generate_pane_image(&window_dims, &panes, &output).await?;
+            render_background(&tmux, &render, &output).await?;
         }
-        Commands::SetBackground {
-            all_panes,
-            keep_file,
-        } => {
-            // Check if we're in a tmux session
-            if !check_tmux_session().await? {
-                anyhow::bail!("Not running in a tmux session. Please start tmux first.");
-            }
-
+        Commands::SetBackground { render, keep_file } => {
             println!("🖼️  Generating background and setting as kitty background...");
             let temp_output = generate_unique_filename("/tmp/kitty-pane-bg-temp.png");
-
-            println!("Getting kitty window information...");
-            let window_dims = get_kitty_window_info().await?;
-            println!(
-                "Window dimensions: {}x{} (cell: {:.1}x{:.1})",
-                window_dims.width,
-                window_dims.height,
-                window_dims.cell_width,
-                window_dims.cell_height
-            );
-
-            println!("Getting tmux pane information...");
-            let panes = if all_panes {
-                tmux::get_tmux_panes().await?
-            } else {
-                get_current_window_panes().await?
-            };
-
-            println!("Found {} panes", panes.len());
-
-            if panes.is_empty() {
-                println!("No tmux panes found. Creating a solid background.");
-            }
-
-            generate_pane_image(&window_dims, &panes, &temp_output).await?;
-
-            // Set as kitty background
-            match set_kitty_background(&temp_output).await {
-                Ok(()) => {
-                    println!("🎨 Successfully set pane layout as kitty background!");
-                }
-                Err(e) => {
-                    eprintln!("⚠️  Failed to set kitty background: {}", e);
-                    eprintln!("   The image was still generated at: {}", temp_output);
-                    eprintln!("   You can manually set it or check kitty remote control setup.");
-                }
-            }
-
-            // Clean up temp file unless requested to keep it
-            if !keep_file {
-                if let Err(e) = std::fs::remove_file(&temp_output) {
-                    eprintln!("Warning: Failed to remove temp file {}: {}", temp_output, e);
-                }
-            } else {
-                println!("📁 Keeping generated file: {}", temp_output);
-            }
+            render_and_set_background(&tmux, &render, &temp_output, keep_file).await?;
         }
-        Commands::Auto {
-            all_panes,
-            keep_file,
-        } => {
-            // Check if we're in a tmux session
-            if !check_tmux_session().await? {
-                anyhow::bail!("Not running in a tmux session. Please start tmux first.");
-            }
-
+        Commands::Auto { render, keep_file } => {
             println!("🚀 Auto mode: Generating background and setting as kitty background...");
             let temp_output = generate_unique_filename("/tmp/kitty-pane-bg-auto.png");
-
-            println!("Getting kitty window information...");
-            let window_dims = get_kitty_window_info().await?;
-            println!(
-                "Window dimensions: {}x{} (cell: {:.1}x{:.1})",
-                window_dims.width,
-                window_dims.height,
-                window_dims.cell_width,
-                window_dims.cell_height
-            );
-
-            println!("Getting tmux pane information...");
-            let panes = if all_panes {
-                tmux::get_tmux_panes().await?
-            } else {
-                get_current_window_panes().await?
-            };
-
-            println!("Found {} panes", panes.len());
-
-            if panes.is_empty() {
-                println!("No tmux panes found. Creating a solid background.");
-            }
-
-            generate_pane_image(&window_dims, &panes, &temp_output).await?;
-
-            // Set as kitty background
-            match set_kitty_background(&temp_output).await {
-                Ok(()) => {
-                    println!("🎨 Successfully set pane layout as kitty background!");
-                }
-                Err(e) => {
-                    eprintln!("⚠️  Failed to set kitty background: {}", e);
-                    eprintln!("   The image was still generated at: {}", temp_output);
-                    eprintln!("   You can manually set it or check kitty remote control setup.");
-                }
-            }
-
-            // Clean up temp file unless requested to keep it
-            if !keep_file {
-                if let Err(e) = std::fs::remove_file(&temp_output) {
-                    eprintln!("Warning: Failed to remove temp file {}: {}", temp_output, e);
-                }
-            } else {
-                println!("📁 Keeping generated file: {}", temp_output);
-            }
+            render_and_set_background(&tmux, &render, &temp_output, keep_file).await?;
+        }
+        Commands::Daemon {
+            render,
+            session,
+            keep_file,
+        } => {
+            run_daemon(&tmux, &render, session.as_deref(), keep_file).await?;
         }
         Commands::InstallHooks => {
             let program_path = std::env::current_exe()
@@ -230,14 +328,14 @@ async fn main() -> Result<()> {
                 .display()
                 .to_string();
 
-            install_tmux_hooks(&program_path).await?;
+            install_tmux_hooks(&tmux, &program_path).await?;
         }
         Commands::Check => {
             println!("🔍 Checking environment...");
             println!();
 
             // Check tmux
-            let in_tmux = check_tmux_session().await?;
+            let in_tmux = tmux.in_session().await;
             println!(
                 "📋 Tmux session: {}",
                 if in_tmux {
@@ -248,11 +346,26 @@ async fn main() -> Result<()> {
             );
 
             // Check kitty with detailed setup info
-            check_kitty_setup().await?;
+            check_kitty_setup(&tmux).await?;
 
             // Check if in tmux and can get panes
             if in_tmux {
-                match get_current_window_panes().await {
+                match tmux.sessions().await {
+                    Ok(sessions) => {
+                        println!("📋 Tmux sessions: ✅ Found {} sessions", sessions.len());
+                        for session in sessions {
+                            println!(
+                                "   • {} {} ({} windows{})",
+                                session.id,
+                                session.name,
+                                session.windows,
+                                if session.attached { ", attached" } else { "" }
+                            );
+                        }
+                    }
+                    Err(e) => println!("📋 Tmux sessions: ❌ Error ({})", e),
+                }
+                match tmux.panes(PaneScope::CurrentWindow).await {
                     Ok(panes) => println!("🔲 Tmux panes: ✅ Found {} panes", panes.len()),
                     Err(e) => println!("🔲 Tmux panes: ❌ Error ({})", e),
                 }
@@ -273,7 +386,7 @@ async fn main() -> Result<()> {
         Commands::Clear => {
             println!("🧹 Clearing kitty background...");
 
-            match clear_kitty_background().await {
+            match clear_kitty_background(&tmux).await {
                 Ok(()) => {
                     println!("✅ Successfully cleared kitty background");
                 }
@@ -286,28 +399,39 @@ async fn main() -> Result<()> {
         }
         Commands::Cache { action } => {
             match action {
-                CacheCommands::Show => {
+                CacheCommands::Show {
+                    sort,
+                    session,
+                    live,
+                    pinned,
+                    json,
+                } => {
                     let cache = ColorCache::load().context("Failed to load color cache")?;
+                    // The cache is still worth showing without a tmux server
+                    let panes = tmux.panes(PaneScope::All).await.unwrap_or_default();
+
+                    let mut entries = cache_entries(&cache, &panes);
+                    filter_entries(&mut entries, session.as_deref(), live, pinned);
+                    sort_entries(&mut entries, sort);
+
+                    if json {
+                        println!(
+                            "{}",
+                            serde_json::to_string_pretty(&entries)
+                                .context("Failed to serialize cache entries")?
+                        );
+                        return Ok(());
+                    }
+
                     println!("Color Cache Information:");
                     println!("Cache file: {:?}", ColorCache::get_cache_path());
                     println!("Startup seed: {}", cache.startup_seed);
                     println!("Cached panes: {}", cache.colors.len());
-                    // Remove opacity printout
-                    if cache.colors.is_empty() {
-                        println!("No colors cached yet.");
+                    if entries.is_empty() {
+                        println!("No matching colors cached.");
                     } else {
-                        println!("\nCached pane colors:");
-                        for (pane_id, cached_color) in &cache.colors {
-                            println!(
-                                "  {} -> RGB({}, {}, {}) Hue:{:.1}° (created: {})",
-                                pane_id,
-                                cached_color.rgb[0],
-                                cached_color.rgb[1],
-                                cached_color.rgb[2],
-                                cached_color.hue,
-                                cached_color.created_at
-                            );
-                        }
+                        println!();
+                        print_table(&entries);
                     }
                 }
                 CacheCommands::Clear => {
@@ -319,6 +443,221 @@ async fn main() -> Result<()> {
                         println!("Color cache was already empty.");
                     }
                 }
+                CacheCommands::Set { pane, color } => {
+                    let rgb = parse_hex_color(&color)
+                        .or_else(|| named_color(&color))
+                        .with_context(|| format!("Unknown color '{}'", color))?;
+                    let mut cache = ColorCache::load().context("Failed to load color cache")?;
+                    let key = resolve_color_key(&tmux, &cache, &pane).await?;
+                    cache.pin_color(&key, rgb);
+                    cache.save().context("Failed to save color cache")?;
+                    println!("📌 Pinned {} to {}", key, color);
+                }
+                CacheCommands::Lock { ref pane } | CacheCommands::Unlock { ref pane } => {
+                    let lock = matches!(action, CacheCommands::Lock { .. });
+                    let mut cache = ColorCache::load().context("Failed to load color cache")?;
+                    let key = resolve_color_key(&tmux, &cache, pane).await?;
+                    if cache.set_pinned(&key, lock) {
+                        cache.save().context("Failed to save color cache")?;
+                        println!("{} {}", if lock { "🔒 Locked" } else { "🔓 Unlocked" }, key);
+                    } else {
+                        println!("Pane {} not found in cache.", key);
+                    }
+                }
+                CacheCommands::Reroll { pane, all } => {
+                    let mut cache = ColorCache::load().context("Failed to load color cache")?;
+                    // Generate with the contrast and tone a render would use,
+                    // within the palette and mode the colors were made with
+                    render_options(&tmux, &RenderArgs::default())
+                        .await?
+                        .follow_cache(&cache)?
+                        .configure(&mut cache);
+                    if all {
+                        let config = Config::load().context("Failed to load config")?;
+                        if config.seed.is_some() {
+                            anyhow::bail!(
+                                "The seed is fixed in {}; change it there to reroll every color",
+                                Config::get_config_path().display()
+                            );
+                        }
+                        let count = cache.reroll_all();
+                        println!("🎲 Rerolled {} colors with a new seed", count);
+                    } else if let Some(pane) = pane {
+                        let key = resolve_color_key(&tmux, &cache, &pane).await?;
+                        // Family colors are rerolled within the pane's window
+                        let panes = tmux.panes(PaneScope::All).await.unwrap_or_default();
+                        if cache.reroll(&key, &panes) {
+                            println!("🎲 Rerolled {}", key);
+                        } else if cache.colors.contains_key(&key) {
+                            println!("Pane {} is pinned; unlock it first.", key);
+                        } else {
+                            println!("Pane {} not found in cache.", key);
+                        }
+                    }
+                    cache.save().context("Failed to save color cache")?;
+                    println!("   Run 'kitty-pane-bg set-background' to apply.");
+                }
+                CacheCommands::Export {
+                    output,
+                    pinned_only,
+                } => {
+                    let cache = ColorCache::load().context("Failed to load color cache")?;
+                    let session_names = session_names(&tmux).await;
+                    let content =
+                        PortableCache::from_cache(&cache, pinned_only, &session_names).to_json()?;
+                    match output {
+                        Some(path) => {
+                            std::fs::write(&path, content + "\n")
+                                .with_context(|| format!("Failed to write {}", path))?;
+                            eprintln!("✅ Exported color cache to {}", path);
+                        }
+                        None => println!("{}", content),
+                    }
+                }
+                CacheCommands::Import { file, merge } => {
+                    let content = std::fs::read_to_string(&file)
+                        .with_context(|| format!("Failed to read {}", file))?;
+                    let portable = PortableCache::from_json(&content)?;
+                    let mut cache = ColorCache::load().context("Failed to load color cache")?;
+                    if portable.key_strategy != cache.key_strategy {
+                        eprintln!(
+                            "⚠️  Colors were exported with the {:?} key strategy but the cache uses {:?}; keys may not match any pane",
+                            portable.key_strategy, cache.key_strategy
+                        );
+                    }
+
+                    let session_names = session_names(&tmux).await;
+                    let summary = portable.merge_into(&mut cache, merge, &session_names)?;
+                    cache.save().context("Failed to save color cache")?;
+                    println!(
+                        "✅ Imported colors: {} added, {} replaced, {} kept",
+                        summary.added, summary.replaced, summary.kept
+                    );
+                }
+                CacheCommands::Servers { prune } => {
+                    let caches = ColorCache::server_caches();
+                    if caches.is_empty() {
+                        println!("No color caches yet.");
+                        return Ok(());
+                    }
+
+                    for server_cache in caches {
+                        let live = server_cache.is_live();
+                        if prune && !live && !server_cache.is_current() {
+                            server_cache.remove()?;
+                            println!(
+                                "🧹 Removed {} ({} colors)",
+                                server_cache.server.as_deref().unwrap_or("no server"),
+                                server_cache.colors
+                            );
+                        } else if !prune {
+                            println!(
+                                "  {} -> {} colors{}{}",
+                                server_cache.server.as_deref().unwrap_or("(no server)"),
+                                server_cache.colors,
+                                if live { "" } else { " [gone]" },
+                                if server_cache.is_current() {
+                                    " [current]"
+                                } else {
+                                    ""
+                                }
+                            );
+                        }
+                    }
+                }
+                CacheCommands::Gc { dry_run } => {
+                    let config = Config::load().context("Failed to load config")?;
+                    let mut cache = ColorCache::load().context("Failed to load color cache")?;
+                    cache.set_retention(config.retention);
+                    // Without a tmux server no pane is live
+                    let panes = tmux.panes(PaneScope::All).await.unwrap_or_default();
+                    let live_keys: Vec<String> =
+                        panes.iter().map(|pane| cache.key_for(pane)).collect();
+                    let evicted = cache.gc(&live_keys);
+                    if dry_run {
+                        println!(
+                            "Would evict {} of {} colors",
+                            evicted,
+                            evicted + cache.colors.len()
+                        );
+                    } else {
+                        cache.save().context("Failed to save color cache")?;
+                        println!(
+                            "🧹 Evicted {} colors, {} remain",
+                            evicted,
+                            cache.colors.len()
+                        );
+                    }
+                }
+                CacheCommands::Preview { simulate, no_image } => {
+                    let cache = ColorCache::load_unsaved().context("Failed to load color cache")?;
+                    if cache.colors.is_empty() {
+                        println!("No colors cached yet.");
+                        return Ok(());
+                    }
+
+                    let mut entries: Vec<_> = cache.colors.iter().collect();
+                    entries.sort_by(|a, b| a.0.cmp(b.0));
+                    let colors: Vec<image::Rgb<u8>> = entries
+                        .iter()
+                        .map(|(_, cached_color)| image::Rgb(cached_color.rgb))
+                        .collect();
+                    let seen = |color: image::Rgb<u8>| match simulate {
+                        Some(color_vision) => color_vision.simulate(color),
+                        None => color,
+                    };
+
+                    if !no_image {
+                        display_inline(&render_swatches(&colors, simulate))?;
+                    }
+
+                    match simulate {
+                        Some(color_vision) => println!(
+                            "Cached colors as seen with {}:",
+                            format!("{:?}", color_vision).to_lowercase()
+                        ),
+                        None => println!("Cached colors:"),
+                    }
+                    for (index, ((pane_id, _), &color)) in entries.iter().zip(&colors).enumerate() {
+                        let simulated = match simulate {
+                            Some(_) => format!("{}  ", ansi_swatch(seen(color), 6)),
+                            None => String::new(),
+                        };
+                        println!(
+                            "  {:>3}  {}  {}{}",
+                            index + 1,
+                            ansi_swatch(color, 6),
+                            simulated,
+                            pane_id
+                        );
+                    }
+
+                    // Report the pair that is hardest to tell apart
+                    let mut closest: Option<(f32, &str, &str)> = None;
+                    for (i, (key_a, a)) in entries.iter().enumerate() {
+                        for (key_b, b) in &entries[i + 1..] {
+                            let distance =
+                                delta_e(seen(image::Rgb(a.rgb)), seen(image::Rgb(b.rgb)));
+                            if closest.is_none_or(|(best, _, _)| distance < best) {
+                                closest = Some((distance, key_a.as_str(), key_b.as_str()));
+                            }
+                        }
+                    }
+                    if let Some((distance, key_a, key_b)) = closest {
+                        println!();
+                        println!(
+                            "Closest pair: {} and {} (ΔE {:.1}{})",
+                            key_a,
+                            key_b,
+                            distance,
+                            if simulate.is_some() {
+                                " when simulated"
+                            } else {
+                                ""
+                            }
+                        );
+                    }
+                }
                 CacheCommands::Remove { pane_id } => {
                     let mut cache = ColorCache::load().context("Failed to load color cache")?;
                     if cache.remove_pane(&pane_id) {
@@ -330,7 +669,440 @@ async fn main() -> Result<()> {
                 }
             }
         }
+        Commands::Palette { action } => match action {
+            PaletteCommands::Preview { name, no_image } => {
+                let palette = find_palette(&name)?;
+                // Without tmux there is no layout to try the palette on
+                let panes = tmux
+                    .panes(PaneScope::CurrentWindow)
+                    .await
+                    .unwrap_or_default();
+
+                if panes.is_empty() {
+                    let colors: Vec<image::Rgb<u8>> = palette.rgb_colors().collect();
+                    if !no_image {
+                        display_inline(&render_swatches(&colors, None))?;
+                    }
+                    println!("Palette {} ({} colors)", palette.name, colors.len());
+                    return Ok(());
+                }
+
+                // Assign on a copy of the cache that is never saved, set up
+                // like a render so the colors are the ones it would draw
+                let mut cache = ColorCache::load_unsaved().context("Failed to load color cache")?;
+                let mut options = render_options(&tmux, &RenderArgs::default()).await?;
+                options.palette = Some(palette.clone());
+                options.configure(&mut cache);
+                cache.assign_pane_colors(&panes);
+                let colors: Vec<image::Rgb<u8>> =
+                    panes.iter().map(|pane| cache.display_color(pane)).collect();
+
+                if !no_image {
+                    display_inline(&render_layout(&panes, &colors))?;
+                }
+                println!("Current window with palette {}:", palette.name);
+                for (index, (pane, &color)) in panes.iter().zip(&colors).enumerate() {
+                    println!(
+                        "  {:>3}  {}  {}  {}",
+                        index + 1,
+                        ansi_swatch(color, 6),
+                        pane.id,
+                        pane.title
+                    );
+                }
+            }
+            PaletteCommands::List => {
+                let config = Config::load().context("Failed to load config")?;
+                println!("Available palettes:");
+                for palette in all_palettes() {
+                    let active = config
+                        .palette
+                        .as_deref()
+                        .is_some_and(|name| name.eq_ignore_ascii_case(&palette.name));
+                    println!(
+                        "  {}{} ({} colors)",
+                        palette.name,
+                        if active { " [configured]" } else { "" },
+                        palette.colors.len()
+                    );
+                }
+            }
+            PaletteCommands::Import { theme, name } => {
+                let palette = import_kitty_theme(&theme, name.as_deref())?;
+                let path = palette.save()?;
+                println!(
+                    "✅ Imported palette '{}' with {} colors",
+                    palette.name,
+                    palette.colors.len()
+                );
+                println!("   Saved to: {}", path.display());
+                println!(
+                    "   Use it with: kitty-pane-bg set-background --palette {}",
+                    palette.name
+                );
+            }
+        },
+    }
+
+    Ok(())
+}
+
+/// Maps a pane id (`%3`) or a full cache key (`@1:%3`) to a cache key,
+/// using the key strategy the cache was last rendered with
+async fn resolve_color_key<R: CommandRunner>(
+    tmux: &TmuxClient<R>,
+    cache: &ColorCache,
+    pane: &str,
+) -> Result<String> {
+    if cache.colors.contains_key(pane) {
+        return Ok(pane.to_string());
+    }
+
+    let panes = tmux.panes(PaneScope::All).await?;
+    match panes.iter().find(|p| p.id == pane) {
+        Some(tmux_pane) => Ok(cache.key_for(tmux_pane)),
+        None => anyhow::bail!("No tmux pane or cached color named '{}'", pane),
+    }
+}
+
+/// Names of the live sessions by id, which session hues are exported and
+/// imported under. Empty without a tmux server.
+async fn session_names<R: CommandRunner>(tmux: &TmuxClient<R>) -> HashMap<String, String> {
+    let sessions = tmux.sessions().await.unwrap_or_default();
+    sessions
+        .into_iter()
+        .map(|session| (session.id, session.name))
+        .collect()
+}
+
+/// Color settings from the config, overridden by the command line, and kitty's
+/// current colors
+async fn render_options<R: CommandRunner>(
+    tmux: &TmuxClient<R>,
+    render: &RenderArgs,
+) -> Result<RenderOptions> {
+    let config = Config::load().context("Failed to load config")?;
+    let min_contrast = render.min_contrast.unwrap_or(config.min_contrast);
+    let color_vision = render.cvd.or(config.cvd);
+    let key_strategy = render.key_strategy.unwrap_or(config.key_strategy);
+    let palette = render
+        .palette
+        .as_ref()
+        .or(config.palette.as_ref())
+        .map(|name| find_palette(name))
+        .transpose()?;
+
+    // Theme and text colors both come from kitty's current color scheme
+    let kitty_colors = match get_kitty_colors(tmux).await {
+        Ok(colors) => Some(colors),
+        Err(e) => {
+            eprintln!(
+                "Warning: Could not read kitty colors, using defaults: {}",
+                e
+            );
+            None
+        }
+    };
+
+    let text_contrast = kitty_colors
+        .as_ref()
+        .filter(|_| min_contrast > 0.0)
+        .map(|colors| {
+            TextContrast::new(
+                colors.foreground,
+                colors.background,
+                &colors.ansi,
+                min_contrast,
+            )
+        });
+
+    Ok(RenderOptions {
+        minimap: None,
+        live_panes: Vec::new(),
+        text_contrast,
+        theme: kitty_colors.map(|colors| Theme::from_background(colors.background)),
+        palette,
+        color_vision,
+        key_strategy,
+        harmony: render.harmony.or(config.harmony),
+        seed: render.seed.or(config.seed),
+        split_tree: render.split_tree || config.split_tree,
+        session_families: render.session_families || config.session_families,
+        rebalance: render
+            .rebalance
+            .map(|stability| Rebalance { stability })
+            .or(config.rebalance),
+        color_shift: config
+            .schedule
+            .map(|schedule| schedule.shift_at(render.at.unwrap_or_else(TimeOfDay::now)))
+            .unwrap_or_default(),
+        retention: config.retention,
+    })
+}
+
+async fn render_background<R: CommandRunner>(
+    tmux: &TmuxClient<R>,
+    render: &RenderArgs,
+    output: &str,
+) -> Result<()> {
+    println!("Getting kitty window information...");
+    let window_dims = get_kitty_window_info(tmux).await?;
+    println!(
+        "Window dimensions: {}x{} (cell: {:.1}x{:.1})",
+        window_dims.width, window_dims.height, window_dims.cell_width, window_dims.cell_height
+    );
+
+    println!("Getting tmux pane information...");
+    let scope = if render.all_panes {
+        PaneScope::All
+    } else {
+        PaneScope::CurrentWindow
+    };
+    let panes = tmux.panes(scope).await?;
+
+    println!("Found {} panes", panes.len());
+
+    if panes.is_empty() {
+        println!("No tmux panes found. Creating a solid background.");
+    }
+
+    let mut options = render_options(tmux, render).await?;
+    options.minimap = match render.minimap {
+        Some(corner) => Some(Minimap::for_current_session(tmux, corner).await?),
+        None => None,
+    };
+    options.live_panes = if render.all_panes {
+        panes.clone()
+    } else {
+        tmux.panes(PaneScope::All).await?
+    };
+
+    generate_pane_image(&window_dims, &panes, output, options).await
+}
+
+async fn render_and_set_background<R: CommandRunner>(
+    tmux: &TmuxClient<R>,
+    render: &RenderArgs,
+    temp_output: &str,
+    keep_file: bool,
+) -> Result<()> {
+    // Check if we're in a tmux session
+    if !tmux.in_session().await {
+        anyhow::bail!("Not running in a tmux session. Please start tmux first.");
+    }
+
+    render_background(tmux, render, temp_output).await?;
+
+    // Set as kitty background
+    match set_kitty_background(tmux, temp_output).await {
+        Ok(()) => {
+            println!("🎨 Successfully set pane layout as kitty background!");
+        }
+        Err(e) => {
+            eprintln!("⚠️  Failed to set kitty background: {}", e);
+            eprintln!("   The image was still generated at: {}", temp_output);
+            eprintln!("   You can manually set it or check kitty remote control setup.");
+        }
+    }
+
+    // Clean up temp file unless requested to keep it
+    if !keep_file {
+        if let Err(e) = std::fs::remove_file(temp_output) {
+            eprintln!("Warning: Failed to remove temp file {}: {}", temp_output, e);
+        }
+    } else {
+        println!("📁 Keeping generated file: {}", temp_output);
+    }
+
+    Ok(())
+}
+
+// Quiet period after a notification before drawing, since one change
+// usually arrives as a burst (a split resizes and refocuses panes)
+const DAEMON_DEBOUNCE: Duration = Duration::from_millis(150);
+// Longest a steady stream of notifications can hold back a redraw
+const DAEMON_MAX_DELAY: Duration = Duration::from_secs(1);
+
+/// The session the daemon draws, and the window it is showing
+#[derive(Debug, Default)]
+struct FollowedSession {
+    session_id: Option<String>,
+    window_id: Option<String>,
+    /// Switch along when another client changes sessions, unless a session
+    /// was picked with `-t`
+    follow_clients: bool,
+}
+
+impl FollowedSession {
+    // Until the current window is known, every window counts as shown
+    fn shows(&self, window_id: &str) -> bool {
+        self.window_id.as_deref().is_none_or(|id| id == window_id)
+    }
+}
+
+/// Follows a session through a control mode client and redraws the
+/// background after each burst of layout, window or session changes. Without
+/// a session to follow, the daemon switches along when another client
+/// switches sessions. The background goes to the kitty found from the
+/// daemon's own environment, like for `set-background`.
+async fn run_daemon<R: CommandRunner>(
+    tmux: &TmuxClient<R>,
+    render: &RenderArgs,
+    session: Option<&str>,
+    keep_file: bool,
+) -> Result<()> {
+    let mut control = ControlClient::attach(tmux, session)?;
+    // Known from the %session-changed sent right after attaching
+    let mut followed = FollowedSession {
+        follow_clients: session.is_none(),
+        ..Default::default()
+    };
+    let minimap = render.minimap.is_some();
+
+    println!("👂 Following tmux changes in control mode (Ctrl-C to stop)...");
+    while let Some(event) = control.next_event().await? {
+        let mut redraw =
+            handle_control_event(tmux, &mut control, &mut followed, minimap, event).await?;
+
+        let deadline = tokio::time::Instant::now() + DAEMON_MAX_DELAY;
+        loop {
+            let quiet_until = (tokio::time::Instant::now() + DAEMON_DEBOUNCE).min(deadline);
+            match tokio::time::timeout_at(quiet_until, control.next_event()).await {
+                Ok(Ok(Some(event))) => {
+                    redraw |= handle_control_event(tmux, &mut control, &mut followed, minimap, event)
+                        .await?;
+                }
+                Ok(Ok(None)) => return Ok(()),
+                Ok(Err(e)) => return Err(e),
+                Err(_) => break,
+            }
+        }
+
+        let Some(session_id) = followed.session_id.as_deref().filter(|_| redraw) else {
+            continue;
+        };
+        let output = generate_unique_filename("/tmp/kitty-pane-bg-daemon.png");
+        let session_tmux = tmux.targeting(session_id);
+        if let Err(e) = render_and_set_background(&session_tmux, render, &output, keep_file).await {
+            eprintln!("Warning: Failed to update background: {}", e);
+        }
     }
 
+    println!("tmux control mode client exited");
     Ok(())
 }
+
+// Returns whether the event changes what the followed session shows
+async fn handle_control_event<R: CommandRunner>(
+    tmux: &TmuxClient<R>,
+    control: &mut ControlClient,
+    followed: &mut FollowedSession,
+    minimap: bool,
+    event: ControlEvent,
+) -> Result<bool> {
+    let redraw = match event {
+        ControlEvent::SessionChanged { session_id } => {
+            // Later window switches are announced, the first window is not
+            followed.window_id = tmux
+                .targeting(&session_id)
+                .current_window_id()
+                .await
+                .ok();
+            followed.session_id = Some(session_id);
+            true
+        }
+        ControlEvent::ClientSessionChanged { session_id, .. } => {
+            // Answered with a %session-changed, which redraws
+            if followed.follow_clients && followed.session_id.as_ref() != Some(&session_id) {
+                control
+                    .send(&format!("switch-client -t {}", session_id))
+                    .await?;
+            }
+            false
+        }
+        ControlEvent::SessionWindowChanged {
+            session_id,
+            window_id,
+        } => {
+            let ours = followed.session_id.as_ref() == Some(&session_id);
+            if ours {
+                followed.window_id = Some(window_id);
+            }
+            ours
+        }
+        // Only sent for windows of the attached session, which the minimap
+        // shows even when they are not current
+        ControlEvent::LayoutChange { window_id } => minimap || followed.shows(&window_id),
+        // Sent for windows of every session
+        ControlEvent::WindowPaneChanged { window_id, .. } => followed.shows(&window_id),
+    };
+
+    Ok(redraw)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tmux::fake::{pane_line, ScriptedRunner};
+
+    #[tokio::test]
+    async fn color_keys_resolve_from_cache_keys_and_pane_ids() {
+        let runner = ScriptedRunner::new()
+            .respond(&["list-panes", "-a"], &pane_line("%3", "@1", "80", "", "zsh"));
+        let tmux = TmuxClient::with_runner(runner);
+        let mut cache = ColorCache::new();
+        cache.pin_color("@9:%9", image::Rgb([1, 2, 3]));
+
+        assert_eq!(resolve_color_key(&tmux, &cache, "@9:%9").await.unwrap(), "@9:%9");
+        assert_eq!(resolve_color_key(&tmux, &cache, "%3").await.unwrap(), "@1:%3");
+        assert!(resolve_color_key(&tmux, &cache, "%7").await.is_err());
+        // Cached keys are found without asking tmux
+        assert_eq!(tmux.runner().calls().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn control_events_follow_the_session_and_its_window() {
+        let runner = ScriptedRunner::new()
+            .respond(&["-C", "attach-session"], "")
+            .respond(&["display-message", "-p", "-t", "$1"], "@4\n");
+        let tmux = TmuxClient::with_runner(runner);
+        let mut control = ControlClient::attach(&tmux, None).unwrap();
+        let mut followed = FollowedSession {
+            follow_clients: true,
+            ..Default::default()
+        };
+        let events = [
+            (
+                ControlEvent::SessionChanged {
+                    session_id: "$1".to_string(),
+                },
+                true,
+            ),
+            // A window of another session
+            (
+                ControlEvent::WindowPaneChanged {
+                    window_id: "@5".to_string(),
+                    pane_id: "%9".to_string(),
+                },
+                false,
+            ),
+            // Followed by switching the control client along
+            (
+                ControlEvent::ClientSessionChanged {
+                    client: "/dev/pts/3".to_string(),
+                    session_id: "$2".to_string(),
+                },
+                false,
+            ),
+        ];
+
+        for (event, redraw) in events {
+            let handled = handle_control_event(&tmux, &mut control, &mut followed, false, event);
+            assert_eq!(handled.await.unwrap(), redraw);
+        }
+
+        assert_eq!(followed.session_id.as_deref(), Some("$1"));
+        assert_eq!(followed.window_id.as_deref(), Some("@4"));
+        assert_eq!(tmux.runner().sent(), ["switch-client -t $2"]);
+    }
+}
@@ -0,0 +1,250 @@
+use crate::color_cache::ColorCache;
+use crate::tmux::{CommandRunner, PaneScope, TmuxClient, TmuxPane};
+use anyhow::Result;
+use clap::ValueEnum;
+use image::{Rgb, RgbImage};
+
+const MARGIN: u32 = 12;
+const GAP: u32 = 6;
+const MIN_THUMB_WIDTH: u32 = 48;
+const THUMB_BACKGROUND: Rgb<u8> = Rgb([35, 35, 35]);
+const CURRENT_OUTLINE: Rgb<u8> = Rgb([240, 240, 240]);
+const OTHER_OUTLINE: Rgb<u8> = Rgb([90, 90, 90]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+#[derive(Debug)]
+pub struct MinimapWindow {
+    pub panes: Vec<TmuxPane>,
+    pub current: bool,
+}
+
+/// Thumbnail overview of every window in the current session
+#[derive(Debug)]
+pub struct Minimap {
+    pub corner: Corner,
+    pub windows: Vec<MinimapWindow>,
+}
+
+impl Minimap {
+    /// Builds the minimap from the `-a` pane listing, grouped by window
+    pub async fn for_current_session<R: CommandRunner>(
+        tmux: &TmuxClient<R>,
+        corner: Corner,
+    ) -> Result<Self> {
+        let all_panes = tmux.panes(PaneScope::All).await?;
+        let session_windows = tmux.windows().await?;
+        let current_window = tmux.current_window_id().await?;
+
+        let windows = session_windows
+            .into_iter()
+            .map(|window| window.id)
+            .map(|window_id| {
+                let panes: Vec<TmuxPane> = all_panes
+                    .iter()
+                    .filter(|p| p.window_id == window_id)
+                    .cloned()
+                    .collect();
+                MinimapWindow {
+                    current: window_id == current_window,
+                    panes,
+                }
+            })
+            .filter(|w| !w.panes.is_empty())
+            .collect();
+
+        Ok(Self { corner, windows })
+    }
+
+    pub fn panes(&self) -> impl Iterator<Item = &TmuxPane> {
+        self.windows.iter().flat_map(|w| w.panes.iter())
+    }
+
+    pub fn draw(&self, image: &mut RgbImage, color_cache: &mut ColorCache) {
+        if self.windows.is_empty() {
+            return;
+        }
+
+        let (image_width, image_height) = image.dimensions();
+        let thumb_width = std::cmp::max(MIN_THUMB_WIDTH, image_width / 8);
+        let thumb_height = std::cmp::max(1, thumb_width * image_height / image_width.max(1));
+
+        // Square-ish grid, filled row by row
+        let count = self.windows.len() as u32;
+        let columns = (count as f32).sqrt().ceil() as u32;
+        let rows = count.div_ceil(columns);
+
+        let grid_width = columns * thumb_width + (columns - 1) * GAP;
+        let grid_height = rows * thumb_height + (rows - 1) * GAP;
+
+        let origin_x = match self.corner {
+            Corner::TopLeft | Corner::BottomLeft => MARGIN,
+            Corner::TopRight | Corner::BottomRight => {
+                image_width.saturating_sub(grid_width + MARGIN)
+            }
+        };
+        let origin_y = match self.corner {
+            Corner::TopLeft | Corner::TopRight => MARGIN,
+            Corner::BottomLeft | Corner::BottomRight => {
+                image_height.saturating_sub(grid_height + MARGIN)
+            }
+        };
+
+        for (index, window) in self.windows.iter().enumerate() {
+            let index = index as u32;
+            let x = origin_x + (index % columns) * (thumb_width + GAP);
+            let y = origin_y + (index / columns) * (thumb_height + GAP);
+            draw_thumbnail(image, window, x, y, thumb_width, thumb_height, color_cache);
+        }
+    }
+}
+
+fn draw_thumbnail(
+    image: &mut RgbImage,
+    window: &MinimapWindow,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    color_cache: &mut ColorCache,
+) {
+    fill_rect(image, x, y, width, height, THUMB_BACKGROUND);
+
+    // Leave a one pixel seam so neighbouring panes stay distinguishable
+    for (pane, (left, top, pane_width, pane_height)) in window
+        .panes
+        .iter()
+        .zip(scale_panes(&window.panes, width, height, 1))
+    {
+        let color = color_cache.display_color(pane);
+        fill_rect(image, x + left, y + top, pane_width, pane_height, color);
+    }
+
+    let outline = if window.current {
+        CURRENT_OUTLINE
+    } else {
+        OTHER_OUTLINE
+    };
+    let thickness = if window.current { 2 } else { 1 };
+    draw_outline(image, x, y, width, height, thickness, outline);
+}
+
+fn draw_outline(
+    image: &mut RgbImage,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    thickness: u32,
+    color: Rgb<u8>,
+) {
+    fill_rect(image, x, y, width, thickness, color);
+    fill_rect(
+        image,
+        x,
+        (y + height).saturating_sub(thickness),
+        width,
+        thickness,
+        color,
+    );
+    fill_rect(image, x, y, thickness, height, color);
+    fill_rect(
+        image,
+        (x + width).saturating_sub(thickness),
+        y,
+        thickness,
+        height,
+        color,
+    );
+}
+
+/// Scales the panes' character grid to fit `width` x `height` pixels.
+/// Returns each pane's left, top, width and height, less a `seam` on its
+/// right and bottom edges.
+pub fn scale_panes(
+    panes: &[TmuxPane],
+    width: u32,
+    height: u32,
+    seam: u32,
+) -> Vec<(u32, u32, u32, u32)> {
+    let cols = panes
+        .iter()
+        .map(|p| p.x + p.width)
+        .max()
+        .unwrap_or(1)
+        .max(1);
+    let lines = panes
+        .iter()
+        .map(|p| p.y + p.height)
+        .max()
+        .unwrap_or(1)
+        .max(1);
+    let scale_x = width as f32 / cols as f32;
+    let scale_y = height as f32 / lines as f32;
+
+    panes
+        .iter()
+        .map(|pane| {
+            let left = (pane.x as f32 * scale_x) as u32;
+            let top = (pane.y as f32 * scale_y) as u32;
+            let right = ((pane.x + pane.width) as f32 * scale_x) as u32;
+            let bottom = ((pane.y + pane.height) as f32 * scale_y) as u32;
+            (
+                left,
+                top,
+                right.saturating_sub(left).saturating_sub(seam).max(1),
+                bottom.saturating_sub(top).saturating_sub(seam).max(1),
+            )
+        })
+        .collect()
+}
+
+pub fn fill_rect(image: &mut RgbImage, x: u32, y: u32, width: u32, height: u32, color: Rgb<u8>) {
+    let end_x = std::cmp::min(x.saturating_add(width), image.width());
+    let end_y = std::cmp::min(y.saturating_add(height), image.height());
+
+    for py in y..end_y {
+        for px in x..end_x {
+            image.put_pixel(px, py, color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tmux::fake::{pane_line, ScriptedRunner};
+
+    #[tokio::test]
+    async fn minimap_groups_the_session_panes_by_window() {
+        let panes = [
+            pane_line("%1", "@1", "80", "", "zsh"),
+            pane_line("%2", "@2", "40", "", "vim"),
+            pane_line("%3", "@2", "39", "", "cargo"),
+            // A window of another session
+            pane_line("%4", "@3", "80", "", "htop"),
+        ]
+        .join("\n");
+        let runner = ScriptedRunner::new()
+            .respond(&["list-panes", "-a"], &panes)
+            .respond(&["list-windows"], "@1\n@2\n")
+            .respond(&["display-message"], "@2\n");
+        let tmux = TmuxClient::with_runner(runner);
+
+        let minimap = Minimap::for_current_session(&tmux, Corner::TopRight)
+            .await
+            .unwrap();
+
+        assert_eq!(minimap.windows.len(), 2);
+        assert!(!minimap.windows[0].current);
+        assert!(minimap.windows[1].current);
+        let ids: Vec<&str> = minimap.panes().map(|pane| pane.id.as_str()).collect();
+        assert_eq!(ids, ["%1", "%2", "%3"]);
+    }
+}
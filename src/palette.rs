@@ -0,0 +1,295 @@
+use crate::color::{delta_e, Oklch};
+use crate::kitty::KittyColors;
+use crate::theme::Theme;
+use anyhow::{Context, Result};
+use image::Rgb;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+// ANSI colors below this OKLCH chroma are greys and carry no hue
+const MIN_ACCENT_CHROMA: f32 = 0.04;
+// Derived colors closer than this (CIEDE2000) are treated as duplicates
+const MIN_ACCENT_DELTA_E: f32 = 5.0;
+
+/// A fixed set of colors pane colors are picked from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Palette {
+    pub name: String,
+    pub colors: Vec<[u8; 3]>,
+}
+
+impl Palette {
+    fn builtin(name: &str, hex_colors: &[u32]) -> Self {
+        Self {
+            name: name.to_string(),
+            colors: hex_colors
+                .iter()
+                .map(|&hex| [(hex >> 16) as u8, (hex >> 8) as u8, hex as u8])
+                .collect(),
+        }
+    }
+
+    pub fn rgb_colors(&self) -> impl Iterator<Item = Rgb<u8>> + '_ {
+        self.colors.iter().map(|&rgb| Rgb(rgb))
+    }
+
+    /// Derives a pane palette from a kitty theme: the hues of its chromatic
+    /// ANSI colors and selection background, at the lightness and chroma the
+    /// theme's background calls for
+    pub fn from_kitty_theme(name: &str, theme_colors: &KittyColors) -> Self {
+        let tone = Theme::from_background(theme_colors.background).pane_tone();
+
+        let mut colors: Vec<Rgb<u8>> = Vec::new();
+        let accents = theme_colors
+            .ansi
+            .iter()
+            .chain(theme_colors.selection_background.iter());
+        for &accent in accents {
+            let accent = Oklch::from_rgb(accent);
+            if accent.c < MIN_ACCENT_CHROMA {
+                continue;
+            }
+
+            let derived = Oklch::new(tone.lightness, tone.chroma.min(accent.c), accent.h).to_rgb();
+            if colors
+                .iter()
+                .all(|&existing| delta_e(existing, derived) >= MIN_ACCENT_DELTA_E)
+            {
+                colors.push(derived);
+            }
+        }
+
+        Self {
+            name: name.to_string(),
+            colors: colors.into_iter().map(|rgb| rgb.0).collect(),
+        }
+    }
+
+    pub fn get_palettes_dir() -> PathBuf {
+        dirs::config_dir()
+            .unwrap_or_else(|| PathBuf::from("/tmp"))
+            .join("kitty-pane-bg")
+            .join("palettes")
+    }
+
+    pub fn save(&self) -> Result<PathBuf> {
+        let palettes_dir = Self::get_palettes_dir();
+        fs::create_dir_all(&palettes_dir).context("Failed to create palettes directory")?;
+
+        let path = palettes_dir.join(format!("{}.json", self.name));
+        let content = serde_json::to_string_pretty(self).context("Failed to serialize palette")?;
+        fs::write(&path, content).context("Failed to write palette file")?;
+
+        Ok(path)
+    }
+}
+
+/// Accent colors of well-known terminal themes
+pub fn builtin_palettes() -> Vec<Palette> {
+    vec![
+        Palette::builtin(
+            "catppuccin",
+            &[
+                0xf5e0dc, 0xf2cdcd, 0xf5c2e7, 0xcba6f7, 0xf38ba8, 0xeba0ac, 0xfab387, 0xf9e2af,
+                0xa6e3a1, 0x94e2d5, 0x89dceb, 0x74c7ec, 0x89b4fa, 0xb4befe,
+            ],
+        ),
+        Palette::builtin(
+            "nord",
+            &[
+                0x8fbcbb, 0x88c0d0, 0x81a1c1, 0x5e81ac, 0xbf616a, 0xd08770, 0xebcb8b, 0xa3be8c,
+                0xb48ead,
+            ],
+        ),
+        Palette::builtin(
+            "gruvbox",
+            &[
+                0xfb4934, 0xb8bb26, 0xfabd2f, 0x83a598, 0xd3869b, 0x8ec07c, 0xfe8019, 0xcc241d,
+                0x98971a, 0xd79921, 0x458588, 0xb16286, 0x689d6a, 0xd65d0e,
+            ],
+        ),
+        Palette::builtin(
+            "solarized",
+            &[
+                0xb58900, 0xcb4b16, 0xdc322f, 0xd33682, 0x6c71c4, 0x268bd2, 0x2aa198, 0x859900,
+            ],
+        ),
+        Palette::builtin(
+            "dracula",
+            &[
+                0x8be9fd, 0x50fa7b, 0xffb86c, 0xff79c6, 0xbd93f9, 0xff5555, 0xf1fa8c,
+            ],
+        ),
+    ]
+}
+
+/// Palettes saved with `palette import`
+pub fn imported_palettes() -> Vec<Palette> {
+    let Ok(entries) = fs::read_dir(Palette::get_palettes_dir()) else {
+        return Vec::new();
+    };
+
+    let mut palettes: Vec<Palette> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|path| {
+            let palette = fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|content| Ok(serde_json::from_str::<Palette>(&content)?));
+            match palette {
+                Ok(palette) => Some(palette),
+                Err(e) => {
+                    eprintln!("Warning: Skipping palette {}: {}", path.display(), e);
+                    None
+                }
+            }
+        })
+        .collect();
+
+    palettes.sort_by(|a, b| a.name.cmp(&b.name));
+    palettes
+}
+
+pub fn all_palettes() -> Vec<Palette> {
+    let mut palettes = builtin_palettes();
+    palettes.extend(imported_palettes());
+    palettes
+}
+
+/// Reads a kitty theme file (`color0`..`color15`, `background`, `foreground`,
+/// `selection_background`) and derives a palette named after it
+pub fn import_kitty_theme(path: &str, name: Option<&str>) -> Result<Palette> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read theme file {}", path))?;
+    let theme_colors = crate::kitty::parse_kitty_colors(&content)
+        .with_context(|| format!("Failed to parse kitty theme {}", path))?;
+
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => std::path::Path::new(path)
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string(),
+    };
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_whitespace() {
+                '-'
+            } else {
+                c.to_ascii_lowercase()
+            }
+        })
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+        .collect();
+
+    if name.is_empty() {
+        anyhow::bail!("Palette name is empty; pass one with --name");
+    }
+    if builtin_palettes().iter().any(|p| p.name == name) {
+        anyhow::bail!(
+            "'{}' is a built-in palette; pass another name with --name",
+            name
+        );
+    }
+
+    let palette = Palette::from_kitty_theme(&name, &theme_colors);
+    if palette.colors.is_empty() {
+        anyhow::bail!(
+            "Theme {} has no chromatic colors to build a palette from",
+            path
+        );
+    }
+
+    Ok(palette)
+}
+
+pub fn find_palette(name: &str) -> Result<Palette> {
+    all_palettes()
+        .into_iter()
+        .find(|palette| palette.name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Unknown palette '{}'. Run 'kitty-pane-bg palette list' to see available palettes.",
+                name
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kitty::parse_kitty_colors;
+
+    const THEME: &str = "\
+# A dark theme
+## author: nobody
+
+foreground #cdd6f4
+background #1e1e2e
+
+# Greys carry no hue
+color0 #45475a
+color7 #bac2de
+color1 #f38ba8
+color9 #f38ba8
+color2 #a6e3a1
+color4 #89b4fa
+color5 #zzzzzz
+";
+
+    fn theme_file(file_name: &str, content: &str) -> String {
+        let dir = std::env::temp_dir().join(format!(
+            "kitty-pane-bg-test-palette-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(file_name);
+        fs::write(&path, content).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn theme_palette_takes_the_distinct_chromatic_hues() {
+        let theme_colors = parse_kitty_colors(THEME).unwrap();
+        let palette = Palette::from_kitty_theme("test", &theme_colors);
+
+        // Red, green and blue; the greys, the repeated red and the bad slot
+        // are left out
+        assert_eq!(palette.colors.len(), 3);
+        let tone = Theme::from_background(theme_colors.background).pane_tone();
+        for rgb in palette.rgb_colors() {
+            assert!((Oklch::from_rgb(rgb).l - tone.lightness).abs() < 0.02);
+        }
+    }
+
+    #[test]
+    fn imported_theme_is_named_after_its_file() {
+        let path = theme_file("Catppuccin Mocha.conf", THEME);
+        let palette = import_kitty_theme(&path, None).unwrap();
+        assert_eq!(palette.name, "catppuccin-mocha");
+        assert_eq!(palette.colors.len(), 3);
+
+        let palette = import_kitty_theme(&path, Some("My Theme!")).unwrap();
+        assert_eq!(palette.name, "my-theme");
+    }
+
+    #[test]
+    fn unusable_themes_are_rejected() {
+        let greys = theme_file(
+            "greys.conf",
+            "foreground #ffffff\nbackground #000000\ncolor0 #000000\ncolor7 #c0c0c0\n",
+        );
+        assert!(import_kitty_theme(&greys, None).is_err());
+
+        let no_background = theme_file("no-background.conf", "foreground #ffffff\ncolor1 #ff0000\n");
+        assert!(import_kitty_theme(&no_background, None).is_err());
+
+        let missing = theme_file("missing.conf", "");
+        fs::remove_file(&missing).unwrap();
+        assert!(import_kitty_theme(&missing, None).is_err());
+    }
+}
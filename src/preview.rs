@@ -0,0 +1,217 @@
+use crate::color::{relative_luminance, ColorVision};
+use crate::minimap::{fill_rect, scale_panes};
+use crate::tmux::TmuxPane;
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose, Engine as _};
+use image::{Rgb, RgbImage};
+use std::io::Write;
+
+const TILE_SIZE: u32 = 48;
+const TILE_GAP: u32 = 4;
+const TILES_PER_ROW: u32 = 8;
+const LAYOUT_WIDTH: u32 = 480;
+const LAYOUT_HEIGHT: u32 = 270;
+const PREVIEW_BACKGROUND: Rgb<u8> = Rgb([30, 30, 30]);
+const LABEL_SCALE: u32 = 3;
+const LABEL_MARGIN: u32 = 4;
+// kitty takes the base64 payload in chunks of at most 4096 bytes
+const CHUNK_SIZE: usize = 4096;
+
+// 3x5 digit glyphs, one row per entry, leftmost pixel in the highest bit
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+/// A grid of color tiles labelled 1, 2, ... With a color vision deficiency
+/// the lower half of each tile shows the color as seen with it.
+pub fn render_swatches(colors: &[Rgb<u8>], simulate: Option<ColorVision>) -> RgbImage {
+    let count = colors.len().max(1) as u32;
+    let columns = count.min(TILES_PER_ROW);
+    let rows = count.div_ceil(TILES_PER_ROW);
+    let mut image = RgbImage::from_pixel(
+        TILE_GAP + columns * (TILE_SIZE + TILE_GAP),
+        TILE_GAP + rows * (TILE_SIZE + TILE_GAP),
+        PREVIEW_BACKGROUND,
+    );
+
+    for (index, &color) in colors.iter().enumerate() {
+        let index = index as u32;
+        let x = TILE_GAP + (index % TILES_PER_ROW) * (TILE_SIZE + TILE_GAP);
+        let y = TILE_GAP + (index / TILES_PER_ROW) * (TILE_SIZE + TILE_GAP);
+
+        fill_rect(&mut image, x, y, TILE_SIZE, TILE_SIZE, color);
+        if let Some(color_vision) = simulate {
+            let half = TILE_SIZE / 2;
+            fill_rect(
+                &mut image,
+                x,
+                y + half,
+                TILE_SIZE,
+                TILE_SIZE - half,
+                color_vision.simulate(color),
+            );
+        }
+        draw_label(&mut image, x, y, index as usize + 1, color);
+    }
+
+    image
+}
+
+/// The panes' layout scaled down, each pane in its color and labelled with
+/// its position in `panes` (starting at 1)
+pub fn render_layout(panes: &[TmuxPane], colors: &[Rgb<u8>]) -> RgbImage {
+    let mut image = RgbImage::from_pixel(LAYOUT_WIDTH, LAYOUT_HEIGHT, PREVIEW_BACKGROUND);
+
+    // Leave a seam so neighbouring panes stay distinguishable
+    let rects = scale_panes(panes, LAYOUT_WIDTH, LAYOUT_HEIGHT, 2);
+    for (index, (rect, &color)) in rects.into_iter().zip(colors).enumerate() {
+        let (left, top, width, height) = rect;
+        fill_rect(&mut image, left, top, width, height, color);
+        draw_label(&mut image, left, top, index + 1, color);
+    }
+
+    image
+}
+
+// Draws a number in the top left corner of an area of the given color, in
+// black or white, whichever reads better on it
+fn draw_label(image: &mut RgbImage, x: u32, y: u32, number: usize, background: Rgb<u8>) {
+    let ink = if relative_luminance(background) > 0.18 {
+        Rgb([0, 0, 0])
+    } else {
+        Rgb([255, 255, 255])
+    };
+
+    let glyph_width = 4 * LABEL_SCALE;
+    for (position, digit) in number.to_string().bytes().enumerate() {
+        let glyph = DIGITS[(digit - b'0') as usize];
+        let glyph_x = x + LABEL_MARGIN + position as u32 * glyph_width;
+        for (row, bits) in glyph.iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) != 0 {
+                    fill_rect(
+                        image,
+                        glyph_x + column * LABEL_SCALE,
+                        y + LABEL_MARGIN + row as u32 * LABEL_SCALE,
+                        LABEL_SCALE,
+                        LABEL_SCALE,
+                        ink,
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Shows an image at the cursor with the kitty graphics protocol. Inside
+/// tmux the escape codes are wrapped for passthrough, which needs
+/// `set -g allow-passthrough on`.
+pub fn display_inline(image: &RgbImage) -> Result<()> {
+    let mut png = Vec::new();
+    image
+        .write_to(
+            &mut std::io::Cursor::new(&mut png),
+            image::ImageOutputFormat::Png,
+        )
+        .context("Failed to encode preview image")?;
+    let encoded = general_purpose::STANDARD.encode(&png);
+
+    let mut stdout = std::io::stdout().lock();
+    write_graphics(&mut stdout, &encoded, std::env::var_os("TMUX").is_some())?;
+    writeln!(stdout)?;
+    stdout.flush()?;
+
+    Ok(())
+}
+
+// Sends base64 PNG data as kitty graphics commands in chunks of at most
+// CHUNK_SIZE bytes, each wrapped for tmux passthrough when inside tmux
+fn write_graphics(out: &mut impl Write, encoded: &str, in_tmux: bool) -> std::io::Result<()> {
+    let chunks: Vec<&str> = encoded
+        .as_bytes()
+        .chunks(CHUNK_SIZE)
+        .map(|chunk| std::str::from_utf8(chunk).unwrap_or_default())
+        .collect();
+
+    for (index, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(index + 1 < chunks.len());
+        // PNG data, transmitted and displayed at once, without replies
+        let control = if index == 0 {
+            format!("f=100,a=T,q=2,m={}", more)
+        } else {
+            format!("m={}", more)
+        };
+        let command = format!("\x1b_G{};{}\x1b\\", control, chunk);
+
+        if in_tmux {
+            write!(
+                out,
+                "\x1bPtmux;{}\x1b\\",
+                command.replace('\x1b', "\x1b\x1b")
+            )?;
+        } else {
+            write!(out, "{}", command)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tmux::fake;
+
+    fn pane(id: &str, x: u32, width: u32) -> TmuxPane {
+        TmuxPane {
+            x,
+            width,
+            ..fake::pane(id, "@1")
+        }
+    }
+
+    #[test]
+    fn layout_fills_the_preview_with_a_seam() {
+        let panes = [pane("%1", 0, 79), pane("%2", 80, 80)];
+        let colors = [Rgb([200, 0, 0]), Rgb([0, 0, 200])];
+        let image = render_layout(&panes, &colors);
+
+        assert_eq!(image.dimensions(), (LAYOUT_WIDTH, LAYOUT_HEIGHT));
+        // Left and right halves, apart from the seam between them
+        assert_eq!(*image.get_pixel(100, 200), colors[0]);
+        assert_eq!(*image.get_pixel(LAYOUT_WIDTH / 2 - 2, 200), PREVIEW_BACKGROUND);
+        assert_eq!(*image.get_pixel(400, 200), colors[1]);
+    }
+
+    #[test]
+    fn graphics_are_chunked_inside_the_passthrough_wrapper() {
+        let encoded = "A".repeat(CHUNK_SIZE * 2 + 100);
+        let mut out = Vec::new();
+        write_graphics(&mut out, &encoded, true).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        // Each command, its ESCs doubled, inside a passthrough sequence
+        let chunks: Vec<(&str, &str)> = out
+            .split_terminator("\x1b\x1b\\\x1b\\")
+            .map(|part| {
+                let command = part.strip_prefix("\x1bPtmux;\x1b\x1b_G").unwrap();
+                command.split_once(';').unwrap()
+            })
+            .collect();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0].0, "f=100,a=T,q=2,m=1");
+        assert_eq!(chunks[1].0, "m=1");
+        assert_eq!(chunks[2].0, "m=0");
+        assert!(chunks.iter().all(|(_, chunk)| chunk.len() <= CHUNK_SIZE));
+        assert_eq!(chunks.iter().map(|(_, chunk)| *chunk).collect::<String>(), encoded);
+    }
+}
@@ -0,0 +1,224 @@
+use crate::color::Oklch;
+use anyhow::{Context, Result};
+use chrono::{Local, Timelike};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+// OKLCH hues that warmth pulls towards: orange when positive, blue when negative
+const WARM_HUE: f32 = 60.0;
+const COOL_HUE: f32 = 250.0;
+
+/// Local wall clock time, written `HH:MM`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeOfDay {
+    minutes: u32,
+}
+
+impl TimeOfDay {
+    /// The current local time
+    pub fn now() -> Self {
+        let now = Local::now();
+        Self {
+            minutes: now.hour() * 60 + now.minute(),
+        }
+    }
+}
+
+impl FromStr for TimeOfDay {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (hours, minutes) = value
+            .split_once(':')
+            .with_context(|| format!("Expected HH:MM, got '{}'", value))?;
+        let hours: u32 = hours
+            .parse()
+            .with_context(|| format!("Invalid hour in '{}'", value))?;
+        let minutes: u32 = minutes
+            .parse()
+            .with_context(|| format!("Invalid minute in '{}'", value))?;
+        if hours > 23 || minutes > 59 {
+            anyhow::bail!("Time '{}' is out of range", value);
+        }
+
+        Ok(Self {
+            minutes: hours * 60 + minutes,
+        })
+    }
+}
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl From<TimeOfDay> for String {
+    fn from(time: TimeOfDay) -> Self {
+        format!("{:02}:{:02}", time.minutes / 60, time.minutes % 60)
+    }
+}
+
+/// How pane colors look at one point of the day, relative to their cached
+/// colors
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ColorShift {
+    /// Added to OKLCH lightness
+    #[serde(default)]
+    pub lightness: f32,
+    /// Multiplies OKLCH chroma
+    #[serde(default = "default_saturation")]
+    pub saturation: f32,
+    /// Fraction of the way every hue is pulled towards orange, or towards
+    /// blue when negative
+    #[serde(default)]
+    pub warmth: f32,
+}
+
+fn default_saturation() -> f32 {
+    1.0
+}
+
+impl Default for ColorShift {
+    fn default() -> Self {
+        Self {
+            lightness: 0.0,
+            saturation: default_saturation(),
+            warmth: 0.0,
+        }
+    }
+}
+
+impl ColorShift {
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn apply(&self, color: Oklch) -> Oklch {
+        let target = if self.warmth >= 0.0 {
+            WARM_HUE
+        } else {
+            COOL_HUE
+        };
+        // Shortest way around the wheel, in -180..180
+        let towards = (target - color.h + 540.0).rem_euclid(360.0) - 180.0;
+
+        Oklch::new(
+            (color.l + self.lightness).clamp(0.0, 1.0),
+            (color.c * self.saturation).max(0.0),
+            color.h + towards * self.warmth.abs().min(1.0),
+        )
+    }
+
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        Self {
+            lightness: self.lightness + (other.lightness - self.lightness) * t,
+            saturation: self.saturation + (other.saturation - self.saturation) * t,
+            warmth: self.warmth + (other.warmth - self.warmth) * t,
+        }
+    }
+}
+
+/// A color shift that takes full effect at a time of day
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleStop {
+    pub at: TimeOfDay,
+    #[serde(flatten)]
+    pub shift: ColorShift,
+}
+
+/// Shifts pane colors over the day. Between two stops the shift is
+/// interpolated, wrapping around midnight.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Schedule {
+    pub stops: Vec<ScheduleStop>,
+}
+
+impl Schedule {
+    pub fn shift_at(&self, time: TimeOfDay) -> ColorShift {
+        let mut stops: Vec<&ScheduleStop> = self.stops.iter().collect();
+        stops.sort_by_key(|stop| stop.at);
+
+        let (Some(first), Some(last)) = (stops.first(), stops.last()) else {
+            return ColorShift::default();
+        };
+
+        // The last stop before `time` and the first one after it, either of
+        // which may lie on the other side of midnight
+        let previous = stops
+            .iter()
+            .rev()
+            .find(|stop| stop.at <= time)
+            .unwrap_or(last);
+        let next = stops.iter().find(|stop| stop.at > time).unwrap_or(first);
+
+        let span = (next.at.minutes + MINUTES_PER_DAY - previous.at.minutes) % MINUTES_PER_DAY;
+        if span == 0 {
+            return previous.shift;
+        }
+        let elapsed = (time.minutes + MINUTES_PER_DAY - previous.at.minutes) % MINUTES_PER_DAY;
+
+        previous
+            .shift
+            .lerp(&next.shift, elapsed as f32 / span as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(value: &str) -> TimeOfDay {
+        value.parse().unwrap()
+    }
+
+    fn stop(at: &str, lightness: f32) -> ScheduleStop {
+        ScheduleStop {
+            at: time(at),
+            shift: ColorShift {
+                lightness,
+                ..ColorShift::default()
+            },
+        }
+    }
+
+    #[test]
+    fn time_of_day_parses_hh_mm() {
+        assert_eq!(time("07:30").minutes, 7 * 60 + 30);
+        assert_eq!(time("0:05").minutes, 5);
+        assert_eq!(time("23:59").minutes, MINUTES_PER_DAY - 1);
+        assert_eq!(String::from(time("7:05")), "07:05");
+
+        for invalid in ["24:00", "12:60", "1200", "12:", "ab:cd", "-1:00", ""] {
+            assert!(invalid.parse::<TimeOfDay>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn shift_wraps_past_midnight() {
+        let schedule = Schedule {
+            stops: vec![stop("06:00", 0.0), stop("22:00", -0.4)],
+        };
+
+        // 22:00 to 06:00 spans eight hours across midnight
+        assert_eq!(schedule.shift_at(time("22:00")).lightness, -0.4);
+        assert!((schedule.shift_at(time("00:00")).lightness + 0.3).abs() < 1e-6);
+        assert!((schedule.shift_at(time("04:00")).lightness + 0.1).abs() < 1e-6);
+        assert_eq!(schedule.shift_at(time("06:00")).lightness, 0.0);
+        assert!((schedule.shift_at(time("14:00")).lightness + 0.2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn single_stop_applies_all_day() {
+        let schedule = Schedule {
+            stops: vec![stop("12:00", 0.1)],
+        };
+        assert_eq!(schedule.shift_at(time("03:00")).lightness, 0.1);
+        assert!(Schedule::default().shift_at(time("03:00")).is_identity());
+    }
+}
@@ -0,0 +1,43 @@
+use crate::color::Oklch;
+use crate::color_cache::PaneTone;
+use image::Rgb;
+
+/// Fill used when kitty's colors cannot be read
+pub const DEFAULT_BACKGROUND: Rgb<u8> = Rgb([20, 20, 20]);
+
+// Themes whose background is darker than this OKLCH lightness count as dark
+const DARK_THRESHOLD: f32 = 0.6;
+
+/// The terminal theme pane colors are derived from
+#[derive(Debug, Clone, Copy)]
+pub struct Theme {
+    pub background: Rgb<u8>,
+    pub dark: bool,
+}
+
+impl Theme {
+    pub fn from_background(background: Rgb<u8>) -> Self {
+        Self {
+            background,
+            dark: Oklch::from_rgb(background).l < DARK_THRESHOLD,
+        }
+    }
+
+    /// Dark themes get deep tints just above the background, light themes
+    /// soft washes just below it, so panes stay close to the theme's look
+    pub fn pane_tone(&self) -> PaneTone {
+        let background_lightness = Oklch::from_rgb(self.background).l;
+
+        if self.dark {
+            PaneTone {
+                lightness: (background_lightness + 0.12).min(0.45),
+                chroma: 0.05,
+            }
+        } else {
+            PaneTone {
+                lightness: (background_lightness - 0.07).max(0.75),
+                chroma: 0.045,
+            }
+        }
+    }
+}
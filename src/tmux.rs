@@ -1,5 +1,167 @@
 use anyhow::{Context, Result};
-use tokio::process::Command as AsyncCommand;
+use std::process::Stdio;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, Command as AsyncCommand};
+
+/// tmux server picked on the command line, like tmux's own `-S` and `-L`
+#[derive(Debug, Clone)]
+pub enum ServerTarget {
+    SocketPath(String),
+    SocketName(String),
+}
+
+static SERVER_TARGET: OnceLock<ServerTarget> = OnceLock::new();
+
+/// Directs every tmux command of this process at the given server
+pub fn set_server_target(target: ServerTarget) {
+    let _ = SERVER_TARGET.set(target);
+}
+
+/// A `tmux` command talking to the selected server
+fn tmux_command() -> AsyncCommand {
+    let mut command = AsyncCommand::new("tmux");
+    match SERVER_TARGET.get() {
+        Some(ServerTarget::SocketPath(path)) => {
+            command.args(["-S", path]);
+        }
+        Some(ServerTarget::SocketName(name)) => {
+            command.args(["-L", name]);
+        }
+        None => {}
+    }
+    command
+}
+
+/// Socket path of the tmux server this process talks to: the `-S` path, the
+/// server in `$TMUX`, or whatever tmux itself reports
+pub async fn resolve_socket_path<R: CommandRunner>(tmux: &TmuxClient<R>) -> Option<String> {
+    match SERVER_TARGET.get() {
+        Some(ServerTarget::SocketPath(path)) => return Some(path.clone()),
+        Some(ServerTarget::SocketName(_)) => {}
+        None => {
+            // $TMUX is "<socket path>,<server pid>,<session id>"
+            let from_env = std::env::var("TMUX").ok().and_then(|tmux| {
+                tmux.split(',')
+                    .next()
+                    .filter(|path| !path.is_empty())
+                    .map(str::to_string)
+            });
+            if from_env.is_some() {
+                return from_env;
+            }
+        }
+    }
+
+    tmux.display("#{socket_path}")
+        .await
+        .ok()
+        .filter(|path| !path.is_empty())
+}
+
+/// Runs one tmux command, given its arguments, and returns what it printed
+pub trait CommandRunner {
+    async fn run(&self, args: &[&str]) -> Result<String>;
+
+    /// Starts a tmux command that keeps running, such as a control mode
+    /// client, and connects to its input and output
+    fn spawn(&self, args: &[&str]) -> Result<CommandPipes>;
+}
+
+impl<R: CommandRunner> CommandRunner for &R {
+    async fn run(&self, args: &[&str]) -> Result<String> {
+        (**self).run(args).await
+    }
+
+    fn spawn(&self, args: &[&str]) -> Result<CommandPipes> {
+        (**self).spawn(args)
+    }
+}
+
+/// Input and output of a command started with [`CommandRunner::spawn`]
+pub struct CommandPipes {
+    pub stdin: Box<dyn AsyncWrite + Send + Unpin>,
+    pub stdout: Box<dyn AsyncRead + Send + Unpin>,
+    /// The process, if there is one, killed along with the pipes
+    pub child: Option<Child>,
+}
+
+/// Runs commands on the tmux server picked with [`set_server_target`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TmuxRunner;
+
+impl CommandRunner for TmuxRunner {
+    async fn run(&self, args: &[&str]) -> Result<String> {
+        let subcommand = args.first().copied().unwrap_or_default();
+        let output = tmux_command()
+            .args(args)
+            .output()
+            .await
+            .with_context(|| format!("Failed to execute tmux {}", subcommand))?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "tmux {} failed: {}",
+                subcommand,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        String::from_utf8(output.stdout).context("Failed to parse tmux output as UTF-8")
+    }
+
+    fn spawn(&self, args: &[&str]) -> Result<CommandPipes> {
+        let subcommand = args.first().copied().unwrap_or_default();
+        let mut child = tmux_command()
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to start tmux {}", subcommand))?;
+
+        let stdin = child.stdin.take().context("tmux has no stdin")?;
+        let stdout = child.stdout.take().context("tmux has no stdout")?;
+        Ok(CommandPipes {
+            stdin: Box::new(stdin),
+            stdout: Box::new(stdout),
+            child: Some(child),
+        })
+    }
+}
+
+/// A row of tmux output, listed with a format of one field per column
+trait TmuxRecord: Sized {
+    /// Format variables, tab separated in the query. The last one may
+    /// contain anything, tabs included.
+    const FIELDS: &'static [&'static str];
+
+    fn from_fields(fields: &[&str]) -> Result<Self>;
+}
+
+fn record_format<T: TmuxRecord>() -> String {
+    T::FIELDS.join("\t")
+}
+
+// Lines with the wrong number of fields are skipped
+fn parse_records<T: TmuxRecord>(output: &str) -> Result<Vec<T>> {
+    output
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.splitn(T::FIELDS.len(), '\t').collect::<Vec<_>>())
+        .filter(|fields| fields.len() == T::FIELDS.len())
+        .map(|fields| T::from_fields(&fields))
+        .collect()
+}
+
+fn parse_field<T: FromStr>(value: &str, name: &str) -> Result<T> {
+    value
+        .parse()
+        .ok()
+        .with_context(|| format!("Failed to parse {}", name))
+}
 
 #[derive(Debug, Clone)]
 pub struct TmuxPane {
@@ -11,133 +173,382 @@ pub struct TmuxPane {
     pub height: u32,
     #[allow(dead_code)]
     pub active: bool,
+    /// `$N`, unlike the name kept when the session is renamed
+    pub session_id: String,
+    pub session_name: String,
+    pub window_index: u32,
+    pub pane_index: u32,
+    /// Value of the `@pane-bg-key` user option, if set for this pane
+    pub key_option: Option<String>,
+    /// The window's split tree, see [`crate::layout::parse_window_layout`]
+    pub window_layout: String,
+    pub title: String,
 }
 
-#[derive(Debug)]
-#[allow(dead_code)]
-pub struct TmuxSession {
-    pub name: String,
-    pub windows: Vec<TmuxWindow>,
+impl TmuxRecord for TmuxPane {
+    const FIELDS: &'static [&'static str] = &[
+        "#{pane_id}",
+        "#{window_id}",
+        "#{pane_left}",
+        "#{pane_top}",
+        "#{pane_width}",
+        "#{pane_height}",
+        "#{pane_active}",
+        "#{session_id}",
+        "#{session_name}",
+        "#{window_index}",
+        "#{pane_index}",
+        "#{@pane-bg-key}",
+        "#{window_layout}",
+        "#{pane_title}",
+    ];
+
+    fn from_fields(fields: &[&str]) -> Result<Self> {
+        Ok(Self {
+            id: fields[0].to_string(),
+            window_id: fields[1].to_string(),
+            x: parse_field(fields[2], "pane x position")?,
+            y: parse_field(fields[3], "pane y position")?,
+            width: parse_field(fields[4], "pane width")?,
+            height: parse_field(fields[5], "pane height")?,
+            active: fields[6] == "1",
+            session_id: fields[7].to_string(),
+            session_name: fields[8].to_string(),
+            window_index: parse_field(fields[9], "window index")?,
+            pane_index: parse_field(fields[10], "pane index")?,
+            key_option: Some(fields[11].to_string()).filter(|value| !value.is_empty()),
+            window_layout: fields[12].to_string(),
+            title: fields[13].to_string(),
+        })
+    }
 }
 
-#[derive(Debug)]
-#[allow(dead_code)]
+#[derive(Debug, Clone)]
 pub struct TmuxWindow {
     pub id: String,
+}
+
+impl TmuxRecord for TmuxWindow {
+    const FIELDS: &'static [&'static str] = &["#{window_id}"];
+
+    fn from_fields(fields: &[&str]) -> Result<Self> {
+        Ok(Self {
+            id: fields[0].to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TmuxSession {
+    pub id: String,
+    pub windows: u32,
+    pub attached: bool,
     pub name: String,
-    pub panes: Vec<TmuxPane>,
-}
-
-pub async fn get_tmux_panes() -> Result<Vec<TmuxPane>> {
-    let output = AsyncCommand::new("tmux")
-        .args(&[
-            "list-panes",
-            "-a",
-            "-F",
-            "#{pane_id} #{window_id} #{pane_left} #{pane_top} #{pane_width} #{pane_height} #{pane_active}"
-        ])
-        .output()
-        .await
-        .context("Failed to execute tmux list-panes")?;
+}
 
-    if !output.status.success() {
-        anyhow::bail!(
-            "tmux list-panes failed: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
+impl TmuxRecord for TmuxSession {
+    const FIELDS: &'static [&'static str] = &[
+        "#{session_id}",
+        "#{session_windows}",
+        "#{session_attached}",
+        "#{session_name}",
+    ];
+
+    fn from_fields(fields: &[&str]) -> Result<Self> {
+        Ok(Self {
+            id: fields[0].to_string(),
+            windows: parse_field(fields[1], "session window count")?,
+            // The number of attached clients
+            attached: fields[2] != "0",
+            name: fields[3].to_string(),
+        })
     }
+}
+
+/// A client attached to the server, i.e. a terminal showing a session
+#[derive(Debug, Clone)]
+pub struct AttachedClient {
+    pub pid: u32,
+    pub session_id: String,
+}
 
-    let output_str =
-        String::from_utf8(output.stdout).context("Failed to parse tmux output as UTF-8")?;
+impl TmuxRecord for AttachedClient {
+    const FIELDS: &'static [&'static str] = &["#{client_pid}", "#{session_id}"];
 
-    let mut panes = Vec::new();
-    for line in output_str.lines() {
-        if line.trim().is_empty() {
-            continue;
-        }
+    fn from_fields(fields: &[&str]) -> Result<Self> {
+        Ok(Self {
+            pid: parse_field(fields[0], "client pid")?,
+            session_id: fields[1].to_string(),
+        })
+    }
+}
 
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.len() != 7 {
-            continue;
-        }
+/// Which panes `list-panes` reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaneScope {
+    /// Every pane of the server
+    All,
+    /// The panes of the current window
+    CurrentWindow,
+}
 
-        let pane = TmuxPane {
-            id: parts[0].to_string(),
-            window_id: parts[1].to_string(),
-            x: parts[2]
-                .parse()
-                .context("Failed to parse pane x position")?,
-            y: parts[3]
-                .parse()
-                .context("Failed to parse pane y position")?,
-            width: parts[4].parse().context("Failed to parse pane width")?,
-            height: parts[5].parse().context("Failed to parse pane height")?,
-            active: parts[6] == "1",
-        };
+/// Typed queries against a tmux server, through a [`CommandRunner`]
+#[derive(Debug)]
+pub struct TmuxClient<R = TmuxRunner> {
+    runner: R,
+    /// What "current" means for queries, instead of the calling pane
+    target: Option<String>,
+}
 
-        panes.push(pane);
+impl TmuxClient {
+    /// A client for the server picked with [`set_server_target`]
+    pub fn new() -> Self {
+        Self::with_runner(TmuxRunner)
     }
+}
 
-    Ok(panes)
+impl Default for TmuxClient {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-pub async fn get_current_window_panes() -> Result<Vec<TmuxPane>> {
-    let output = AsyncCommand::new("tmux")
-        .args(&[
-            "list-panes",
-            "-F",
-            "#{pane_id} #{window_id} #{pane_left} #{pane_top} #{pane_width} #{pane_height} #{pane_active}"
-        ])
-        .output()
-        .await
-        .context("Failed to execute tmux list-panes for current window")?;
+impl<R: CommandRunner> TmuxClient<R> {
+    pub fn with_runner(runner: R) -> Self {
+        Self {
+            runner,
+            target: None,
+        }
+    }
 
-    if !output.status.success() {
-        anyhow::bail!(
-            "tmux list-panes failed: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
+    /// Resolves the current session, window and pane from a tmux target
+    /// (`$1`, `@2`, `%3`, ...) rather than from the calling pane, which a
+    /// process outside tmux does not have
+    pub fn with_target(mut self, target: &str) -> Self {
+        self.target = Some(target.to_string());
+        self
     }
 
-    let output_str =
-        String::from_utf8(output.stdout).context("Failed to parse tmux output as UTF-8")?;
+    /// A client over the same runner that resolves the current session,
+    /// window and pane from a tmux target, see [`Self::with_target`]
+    pub fn targeting(&self, target: &str) -> TmuxClient<&R> {
+        TmuxClient::with_runner(&self.runner).with_target(target)
+    }
+
+    /// The runner queries go through, to check what a test ran
+    #[cfg(test)]
+    pub(crate) fn runner(&self) -> &R {
+        &self.runner
+    }
 
-    let mut panes = Vec::new();
-    for line in output_str.lines() {
-        if line.trim().is_empty() {
-            continue;
+    fn push_target<'a>(&'a self, args: &mut Vec<&'a str>) {
+        if let Some(target) = &self.target {
+            args.extend(["-t", target]);
         }
+    }
 
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.len() != 7 {
-            continue;
+    pub async fn panes(&self, scope: PaneScope) -> Result<Vec<TmuxPane>> {
+        let format = record_format::<TmuxPane>();
+        let mut args = vec!["list-panes"];
+        match scope {
+            PaneScope::All => args.push("-a"),
+            PaneScope::CurrentWindow => self.push_target(&mut args),
         }
+        args.extend(["-F", &format]);
+
+        parse_records(&self.runner.run(&args).await?)
+    }
+
+    /// The windows of the current session, in index order
+    pub async fn windows(&self) -> Result<Vec<TmuxWindow>> {
+        let format = record_format::<TmuxWindow>();
+        let mut args = vec!["list-windows"];
+        self.push_target(&mut args);
+        args.extend(["-F", &format]);
+
+        parse_records(&self.runner.run(&args).await?)
+    }
+
+    pub async fn sessions(&self) -> Result<Vec<TmuxSession>> {
+        let format = record_format::<TmuxSession>();
+        parse_records(&self.runner.run(&["list-sessions", "-F", &format]).await?)
+    }
+
+    pub async fn clients(&self) -> Result<Vec<AttachedClient>> {
+        let format = record_format::<AttachedClient>();
+        parse_records(&self.runner.run(&["list-clients", "-F", &format]).await?)
+    }
+
+    /// Expands a format in the context of the current pane
+    pub async fn display(&self, format: &str) -> Result<String> {
+        let mut args = vec!["display-message", "-p"];
+        self.push_target(&mut args);
+        args.push(format);
+        let output = self.runner.run(&args).await?;
+        Ok(output.trim().to_string())
+    }
+
+    /// Whether there is a current session to talk about
+    pub async fn in_session(&self) -> bool {
+        self.display("#{session_name}").await.is_ok()
+    }
+
+    pub async fn current_window_id(&self) -> Result<String> {
+        self.display("#{window_id}").await
+    }
+
+    /// Value of a global option, None when it is unset
+    pub async fn option(&self, name: &str) -> Result<Option<String>> {
+        let output = self.runner.run(&["show-options", "-gqv", name]).await?;
+        let value = output.trim_end_matches('\n');
+        Ok(Some(value.to_string()).filter(|value| !value.is_empty()))
+    }
+
+    pub async fn set_hook(&self, hook: &str, command: &str) -> Result<()> {
+        self.runner.run(&["set-hook", "-g", hook, command]).await?;
+        Ok(())
+    }
+
+    pub async fn run_shell(&self, command: &str) -> Result<()> {
+        self.runner.run(&["run-shell", command]).await?;
+        Ok(())
+    }
+}
+
+/// A notification a control mode client receives, see CONTROL MODE in tmux(1)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlEvent {
+    /// `%layout-change`: panes of a window were split, closed or resized
+    LayoutChange { window_id: String },
+    /// `%window-pane-changed`: another pane of a window became active
+    WindowPaneChanged { window_id: String, pane_id: String },
+    /// `%session-window-changed`: a session switched to another window
+    SessionWindowChanged {
+        session_id: String,
+        window_id: String,
+    },
+    /// `%client-session-changed`: another client switched sessions
+    ClientSessionChanged { client: String, session_id: String },
+    /// `%session-changed`: the control client itself attached to a session
+    SessionChanged { session_id: String },
+}
+
+impl ControlEvent {
+    /// Parses a notification line. None for notifications nothing reacts to.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut words = line.split(' ');
+        let mut word = || words.next().map(str::to_string);
 
-        let pane = TmuxPane {
-            id: parts[0].to_string(),
-            window_id: parts[1].to_string(),
-            x: parts[2]
-                .parse()
-                .context("Failed to parse pane x position")?,
-            y: parts[3]
-                .parse()
-                .context("Failed to parse pane y position")?,
-            width: parts[4].parse().context("Failed to parse pane width")?,
-            height: parts[5].parse().context("Failed to parse pane height")?,
-            active: parts[6] == "1",
+        let event = match word()?.as_str() {
+            "%layout-change" => Self::LayoutChange { window_id: word()? },
+            "%window-pane-changed" => Self::WindowPaneChanged {
+                window_id: word()?,
+                pane_id: word()?,
+            },
+            "%session-window-changed" => Self::SessionWindowChanged {
+                session_id: word()?,
+                window_id: word()?,
+            },
+            "%client-session-changed" => Self::ClientSessionChanged {
+                client: word()?,
+                session_id: word()?,
+            },
+            "%session-changed" => Self::SessionChanged {
+                session_id: word()?,
+            },
+            _ => return None,
         };
 
-        panes.push(pane);
+        Some(event)
+    }
+}
+
+/// A control mode client (`tmux -C`), attached to a session to follow its
+/// notifications as they happen
+pub struct ControlClient {
+    _child: Option<Child>,
+    stdin: Box<dyn AsyncWrite + Send + Unpin>,
+    lines: Lines<BufReader<Box<dyn AsyncRead + Send + Unpin>>>,
+    // Inside a %begin/%end block, where lines are command output
+    in_reply: bool,
+}
+
+impl ControlClient {
+    /// Attaches to the session, or to the most recently used one. The
+    /// client neither receives pane output nor affects window sizes.
+    pub fn attach<R: CommandRunner>(tmux: &TmuxClient<R>, session: Option<&str>) -> Result<Self> {
+        let mut args = vec!["-C", "attach-session", "-f", "ignore-size,no-output"];
+        if let Some(session) = session {
+            args.extend(["-t", session]);
+        }
+
+        let pipes = tmux
+            .runner
+            .spawn(&args)
+            .context("Failed to start tmux in control mode")?;
+
+        Ok(Self {
+            _child: pipes.child,
+            stdin: pipes.stdin,
+            lines: BufReader::new(pipes.stdout).lines(),
+            in_reply: false,
+        })
     }
 
-    Ok(panes)
+    /// Waits for the next notification. None once the client was detached
+    /// or the server exited.
+    pub async fn next_event(&mut self) -> Result<Option<ControlEvent>> {
+        while let Some(line) = self
+            .lines
+            .next_line()
+            .await
+            .context("Failed to read from tmux control mode")?
+        {
+            if self.in_reply {
+                self.in_reply = !(line.starts_with("%end ") || line.starts_with("%error "));
+                continue;
+            }
+            if line.starts_with("%begin ") {
+                self.in_reply = true;
+                continue;
+            }
+            if line.starts_with("%exit") {
+                return Ok(None);
+            }
+
+            if let Some(event) = ControlEvent::parse(&line) {
+                return Ok(Some(event));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Runs a command as this client. Its reply is skipped.
+    pub async fn send(&mut self, command: &str) -> Result<()> {
+        self.stdin
+            .write_all(format!("{}\n", command).as_bytes())
+            .await
+            .context("Failed to send command to tmux control mode")
+    }
 }
 
-pub async fn install_tmux_hooks(program_path: &str) -> Result<()> {
-    let hook_command = format!(
-        "run-shell '{} set-background >/dev/null 2>&1'",
-        program_path
+pub async fn install_tmux_hooks<R: CommandRunner>(
+    tmux: &TmuxClient<R>,
+    program_path: &str,
+) -> Result<()> {
+    // Hooks run with $TMUX set, but pass an explicit server along anyway
+    let server_args = match SERVER_TARGET.get() {
+        Some(ServerTarget::SocketPath(path)) => format!(" -S {}", shell_quote(path)),
+        Some(ServerTarget::SocketName(name)) => format!(" -L {}", shell_quote(name)),
+        None => String::new(),
+    };
+    let shell_command = format!(
+        "{}{} set-background >/dev/null 2>&1",
+        shell_quote(program_path),
+        server_args
     );
+    let hook_command = format!("run-shell {}", tmux_quote(&shell_command));
 
     // Install hooks for pane events
     let hooks = [
@@ -162,22 +573,15 @@ pub async fn install_tmux_hooks(program_path: &str) -> Result<()> {
     let mut failed_count = 0;
 
     for (hook_name, command) in hooks {
-        let output = AsyncCommand::new("tmux")
-            .args(&["set-hook", "-g", hook_name, command])
-            .output()
-            .await
-            .context(format!("Failed to set tmux hook: {}", hook_name))?;
-
-        if !output.status.success() {
-            eprintln!(
-                "Warning: Failed to set hook {}: {}",
-                hook_name,
-                String::from_utf8_lossy(&output.stderr)
-            );
-            failed_count += 1;
-        } else {
-            println!("✅ Installed tmux hook: {}", hook_name);
-            installed_count += 1;
+        match tmux.set_hook(hook_name, command).await {
+            Ok(()) => {
+                println!("✅ Installed tmux hook: {}", hook_name);
+                installed_count += 1;
+            }
+            Err(e) => {
+                eprintln!("Warning: Failed to set hook {}: {}", hook_name, e);
+                failed_count += 1;
+            }
         }
     }
 
@@ -202,14 +606,389 @@ pub async fn install_tmux_hooks(program_path: &str) -> Result<()> {
     Ok(())
 }
 
-pub async fn check_tmux_session() -> Result<bool> {
-    let output = AsyncCommand::new("tmux")
-        .args(&["display-message", "-p", "#{session_name}"])
-        .output()
-        .await;
+// Quotes a word for sh unless it only has characters sh leaves alone
+fn shell_quote(word: &str) -> String {
+    let plain = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./:@%+=,".contains(c));
+    if plain {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+// Quotes a command argument for tmux's command parser. run-shell also
+// expands formats, so '#' is doubled.
+fn tmux_quote(argument: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in argument.chars() {
+        match c {
+            '\\' | '"' | '$' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '#' => quoted.push_str("##"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Stand-ins for a tmux server, so code that talks to tmux can be tested
+/// without one
+#[cfg(test)]
+pub(crate) mod fake {
+    use super::*;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context as TaskContext, Poll};
+
+    /// Answers tmux commands from a script instead of a server
+    #[derive(Debug, Default)]
+    pub(crate) struct ScriptedRunner {
+        responses: Vec<(Vec<String>, Result<String, String>)>,
+        calls: Mutex<Vec<Vec<String>>>,
+        sent: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl ScriptedRunner {
+        pub(crate) fn new() -> Self {
+            Self::default()
+        }
+
+        /// Answers commands whose arguments start with `args` with `stdout`.
+        /// The first matching response wins.
+        pub(crate) fn respond(mut self, args: &[&str], stdout: &str) -> Self {
+            self.responses
+                .push((to_strings(args), Ok(stdout.to_string())));
+            self
+        }
+
+        /// Fails commands whose arguments start with `args`, as tmux would with
+        /// `stderr`
+        pub(crate) fn fail(mut self, args: &[&str], stderr: &str) -> Self {
+            self.responses
+                .push((to_strings(args), Err(stderr.to_string())));
+            self
+        }
+
+        /// Arguments of every command run so far, in order
+        pub(crate) fn calls(&self) -> Vec<Vec<String>> {
+            self.calls
+                .lock()
+                .map(|calls| calls.clone())
+                .unwrap_or_default()
+        }
+
+        /// Lines written to spawned commands, such as control mode commands
+        pub(crate) fn sent(&self) -> Vec<String> {
+            let sent = self.sent.lock().map(|sent| sent.clone()).unwrap_or_default();
+            String::from_utf8_lossy(&sent).lines().map(str::to_string).collect()
+        }
+
+        fn output(&self, args: &[&str]) -> Result<String> {
+            if let Ok(mut calls) = self.calls.lock() {
+                calls.push(to_strings(args));
+            }
+
+            let response = self
+                .responses
+                .iter()
+                .find(|(prefix, _)| {
+                    prefix.len() <= args.len() && prefix.iter().zip(args).all(|(a, b)| a == b)
+                })
+                .map(|(_, response)| response)
+                .with_context(|| format!("No scripted response for tmux {}", args.join(" ")))?;
+
+            match response {
+                Ok(stdout) => Ok(stdout.clone()),
+                Err(stderr) => anyhow::bail!("tmux {} failed: {}", args[0], stderr),
+            }
+        }
+    }
+
+    impl CommandRunner for ScriptedRunner {
+        async fn run(&self, args: &[&str]) -> Result<String> {
+            self.output(args)
+        }
+
+        /// Spawned commands print their scripted response, then exit
+        fn spawn(&self, args: &[&str]) -> Result<CommandPipes> {
+            let stdout = self.output(args)?;
+            Ok(CommandPipes {
+                stdin: Box::new(Recorder(Arc::clone(&self.sent))),
+                stdout: Box::new(std::io::Cursor::new(stdout.into_bytes())),
+                child: None,
+            })
+        }
+    }
+
+    // Keeps whatever is written to it for ScriptedRunner::sent
+    struct Recorder(Arc<Mutex<Vec<u8>>>);
+
+    impl AsyncWrite for Recorder {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _: &mut TaskContext<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            if let Ok(mut sent) = self.0.lock() {
+                sent.extend_from_slice(buf);
+            }
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut TaskContext<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: Pin<&mut Self>,
+            _: &mut TaskContext<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn to_strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    /// An 80x24 pane of session "main" at the top left of its window
+    pub(crate) fn pane(id: &str, window_id: &str) -> TmuxPane {
+        TmuxPane {
+            id: id.to_string(),
+            window_id: window_id.to_string(),
+            x: 0,
+            y: 0,
+            width: 80,
+            height: 24,
+            active: false,
+            session_id: "$0".to_string(),
+            session_name: "main".to_string(),
+            window_index: 0,
+            pane_index: 0,
+            key_option: None,
+            window_layout: String::new(),
+            title: String::new(),
+        }
+    }
+
+    /// A list-panes line in [`TmuxPane`] field order, for a pane of session
+    /// "main" at the left edge of its window
+    pub(crate) fn pane_line(id: &str, window_id: &str, width: &str, key: &str, title: &str) -> String {
+        [
+            id, window_id, "0", "0", width, "24", "1", "$0", "main", "0", "0", key, "layout", title,
+        ]
+        .join("\t")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fake::{pane_line, ScriptedRunner};
+    use super::*;
+
+    #[tokio::test]
+    async fn panes_keep_tabs_in_titles() {
+        let output = format!(
+            "{}\n{}\n",
+            pane_line("%1", "@1", "80", "", "vim\tmain.rs"),
+            pane_line("%2", "@1", "40", "build", "cargo")
+        );
+        let tmux = TmuxClient::with_runner(ScriptedRunner::new().respond(&["list-panes"], &output));
+
+        let panes = tmux.panes(PaneScope::All).await.unwrap();
+
+        assert_eq!(panes.len(), 2);
+        assert_eq!(panes[0].title, "vim\tmain.rs");
+        assert_eq!(panes[0].key_option, None);
+        assert_eq!(panes[1].width, 40);
+        assert_eq!(panes[1].key_option.as_deref(), Some("build"));
+    }
+
+    #[tokio::test]
+    async fn panes_skip_lines_with_too_few_fields() {
+        let output = format!("%9\t@1\t0\n\n{}\n", pane_line("%1", "@1", "80", "", "zsh"));
+        let tmux = TmuxClient::with_runner(ScriptedRunner::new().respond(&["list-panes"], &output));
+
+        let panes = tmux.panes(PaneScope::All).await.unwrap();
+
+        assert_eq!(panes.len(), 1);
+        assert_eq!(panes[0].id, "%1");
+    }
+
+    #[tokio::test]
+    async fn panes_fail_on_a_non_numeric_width() {
+        let output = pane_line("%1", "@1", "wide", "", "zsh");
+        let tmux = TmuxClient::with_runner(ScriptedRunner::new().respond(&["list-panes"], &output));
+
+        let error = tmux.panes(PaneScope::All).await.unwrap_err();
+
+        assert_eq!(error.to_string(), "Failed to parse pane width");
+    }
+
+    #[tokio::test]
+    async fn target_is_passed_to_current_queries() {
+        let runner = ScriptedRunner::new()
+            .respond(&["list-panes"], "")
+            .respond(&["list-windows"], "@1\n@2\n")
+            .respond(&["display-message"], "$3\n");
+        let tmux = TmuxClient::with_runner(runner).with_target("$3");
+
+        tmux.panes(PaneScope::CurrentWindow).await.unwrap();
+        tmux.panes(PaneScope::All).await.unwrap();
+        let windows = tmux.windows().await.unwrap();
+        let session = tmux.display("#{session_id}").await.unwrap();
+
+        assert_eq!(windows.len(), 2);
+        assert_eq!(windows[1].id, "@2");
+        assert_eq!(session, "$3");
+        let calls = tmux.runner.calls();
+        assert_eq!(calls[0][..3], ["list-panes", "-t", "$3"]);
+        assert_eq!(calls[1][..2], ["list-panes", "-a"]);
+        assert_eq!(calls[2][..3], ["list-windows", "-t", "$3"]);
+        assert_eq!(
+            calls[3],
+            ["display-message", "-p", "-t", "$3", "#{session_id}"]
+        );
+    }
+
+    #[tokio::test]
+    async fn untargeted_queries_use_the_calling_pane() {
+        let tmux =
+            TmuxClient::with_runner(ScriptedRunner::new().respond(&["display-message"], "@4"));
+
+        assert_eq!(tmux.current_window_id().await.unwrap(), "@4");
+        assert_eq!(
+            tmux.runner.calls()[0],
+            ["display-message", "-p", "#{window_id}"]
+        );
+    }
 
-    match output {
-        Ok(result) => Ok(result.status.success()),
-        Err(_) => Ok(false),
+    #[tokio::test]
+    async fn clients_are_parsed() {
+        let runner = ScriptedRunner::new().respond(&["list-clients"], "1234\t$0\n5678\t$2\n");
+        let tmux = TmuxClient::with_runner(runner);
+
+        let clients = tmux.clients().await.unwrap();
+
+        assert_eq!(clients.len(), 2);
+        assert_eq!(clients[1].pid, 5678);
+        assert_eq!(clients[1].session_id, "$2");
+    }
+
+    #[tokio::test]
+    async fn sessions_are_parsed() {
+        let runner = ScriptedRunner::new()
+            .respond(&["list-sessions"], "$0\t3\t1\tmain\n$4\t1\t0\tmy notes\n");
+        let tmux = TmuxClient::with_runner(runner);
+
+        let sessions = tmux.sessions().await.unwrap();
+
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].windows, 3);
+        assert!(sessions[0].attached);
+        assert_eq!(sessions[1].id, "$4");
+        assert!(!sessions[1].attached);
+        assert_eq!(sessions[1].name, "my notes");
+    }
+
+    #[tokio::test]
+    async fn unset_options_are_none() {
+        let runner = ScriptedRunner::new()
+            .respond(&["show-options", "-gqv", "allow-passthrough"], "on\n")
+            .respond(&["show-options", "-gqv"], "");
+        let tmux = TmuxClient::with_runner(runner);
+
+        assert_eq!(
+            tmux.option("allow-passthrough").await.unwrap().as_deref(),
+            Some("on")
+        );
+        assert_eq!(tmux.option("@pane-bg-key").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn failed_commands_report_tmux_errors() {
+        let runner = ScriptedRunner::new().fail(&["display-message"], "no current client");
+        let tmux = TmuxClient::with_runner(runner);
+
+        let error = tmux.display("#{session_name}").await.unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "tmux display-message failed: no current client"
+        );
+        assert!(!tmux.in_session().await);
+        assert!(tmux.clients().await.is_err());
+    }
+
+    #[test]
+    fn control_events_are_parsed() {
+        assert_eq!(
+            ControlEvent::parse("%layout-change @2 b25d,80x24,0,0,2 b25d,80x24,0,0,2 *"),
+            Some(ControlEvent::LayoutChange {
+                window_id: "@2".to_string()
+            })
+        );
+        assert_eq!(
+            ControlEvent::parse("%window-pane-changed @2 %5"),
+            Some(ControlEvent::WindowPaneChanged {
+                window_id: "@2".to_string(),
+                pane_id: "%5".to_string()
+            })
+        );
+        assert_eq!(
+            ControlEvent::parse("%session-window-changed $1 @3"),
+            Some(ControlEvent::SessionWindowChanged {
+                session_id: "$1".to_string(),
+                window_id: "@3".to_string()
+            })
+        );
+        assert_eq!(
+            ControlEvent::parse("%client-session-changed /dev/pts/3 $4 work"),
+            Some(ControlEvent::ClientSessionChanged {
+                client: "/dev/pts/3".to_string(),
+                session_id: "$4".to_string()
+            })
+        );
+        assert_eq!(
+            ControlEvent::parse("%session-changed $0 my demo"),
+            Some(ControlEvent::SessionChanged {
+                session_id: "$0".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn other_and_truncated_notifications_are_ignored() {
+        assert_eq!(ControlEvent::parse("%output %1 hello"), None);
+        assert_eq!(ControlEvent::parse("%window-pane-changed @2"), None);
+        assert_eq!(ControlEvent::parse(""), None);
+    }
+
+    #[tokio::test]
+    async fn control_client_skips_command_replies() {
+        let output = "%begin 1 1 0\n%layout-change @9 x\n%end 1 1 0\n\
+                      %session-changed $1 main\n%exit\n%layout-change @2 x\n";
+        let runner = ScriptedRunner::new().respond(&["-C", "attach-session"], output);
+        let tmux = TmuxClient::with_runner(runner);
+
+        let mut control = ControlClient::attach(&tmux, Some("$1")).unwrap();
+        control.send("refresh-client").await.unwrap();
+
+        assert_eq!(
+            control.next_event().await.unwrap(),
+            Some(ControlEvent::SessionChanged {
+                session_id: "$1".to_string()
+            })
+        );
+        assert_eq!(control.next_event().await.unwrap(), None);
+        let calls = tmux.runner().calls();
+        assert_eq!(calls[0][calls[0].len() - 2..], ["-t", "$1"]);
+        assert_eq!(tmux.runner().sent(), ["refresh-client"]);
     }
 }